@@ -0,0 +1,4 @@
+#!/bin/sh
+
+# System user of the systemd units
+systemd-sysusers gdu.conf || true
@@ -8,12 +8,24 @@ source ./scripts/config.sh
 
 OS="linux"
 PKG_INSTALL_BIN_DIR="/usr/bin/"
+PKG_INSTALL_SYSTEMD_DIR="/lib/systemd/system/"
+PKG_INSTALL_SYSUSERS_FILE="/usr/lib/sysusers.d/gdu.conf"
 PKG_NAME="$APP"
 OUTPUT_DIR="$(pwd)/pkg/$ARCH"
 
 DEB_VERSION="$CARGO_VERSION.b$TRAVIS_BUILD_NUMBER-1git+$GIT_VERSION"
 RPM_VERSION="$CARGO_VERSION.b$TRAVIS_BUILD_NUMBER_1git+$GIT_VERSION"
 
+## systemd daemon unit, timer of one-shot updates and their system user
+SYSTEMD_DIR="$(pwd)/scripts/systemd"
+SYSTEMD_UNITS="$SYSTEMD_DIR/gdu.service=$PKG_INSTALL_SYSTEMD_DIR \
+  $SYSTEMD_DIR/gdu-update.service=$PKG_INSTALL_SYSTEMD_DIR \
+  $SYSTEMD_DIR/gdu-update.timer=$PKG_INSTALL_SYSTEMD_DIR \
+  $SYSTEMD_DIR/gdu.sysusers=$PKG_INSTALL_SYSUSERS_FILE"
+
+## gdu system user of the units, created once installed
+AFTER_INSTALL="$(pwd)/scripts/after-install.sh"
+
 if [ -d "$OUTPUT_DIR" ]
 then
   echo "$OUTPUT_DIR found, skip artifacts buildings"
@@ -39,7 +51,9 @@ else
       -v $DEB_VERSION \
       -a $ARCH \
       --vendor $VENDOR \
-      $BUILD_BIN_FILE=$PKG_INSTALL_BIN_DIR
+      --after-install $AFTER_INSTALL \
+      $BUILD_BIN_FILE=$PKG_INSTALL_BIN_DIR \
+      $SYSTEMD_UNITS
 
     ## 64 bits rpm package
     ARCH="x86_64"
@@ -53,7 +67,9 @@ else
       -v $RPM_VERSION \
       -a $ARCH \
       --vendor $VENDOR \
-      $BUILD_BIN_FILE=$PKG_INSTALL_BIN_DIR
+      --after-install $AFTER_INSTALL \
+      $BUILD_BIN_FILE=$PKG_INSTALL_BIN_DIR \
+      $SYSTEMD_UNITS
 
   elif [ "$ARCH" = "armv6" ]
   then
@@ -77,7 +93,9 @@ else
       -v $DEB_VERSION \
       -a $ARCH \
       --vendor $VENDOR \
-      $BUILD_BIN_FILE=$PKG_INSTALL_BIN_DIR
+      --after-install $AFTER_INSTALL \
+      $BUILD_BIN_FILE=$PKG_INSTALL_BIN_DIR \
+      $SYSTEMD_UNITS
 
   else
     echo "Unknown architecture!"
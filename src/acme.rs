@@ -0,0 +1,221 @@
+use dns::ChangeSet;
+use dns::Record;
+use dns::RecordType;
+use dns::RecordValue;
+use dns::ZoneRecord;
+use error::Error;
+use error::Result;
+use openssl::crypto::hash::{Type, hash};
+use propagation;
+use propagation::NameserverStatus;
+use resolver::{Answer, QueryType, Resolver};
+use rustc_serialize::base64::{ToBase64, URL_SAFE};
+use std::result::Result as StdResult;
+use std::str::FromStr;
+use std::time::Duration;
+
+// ACME DNS-01 challenges (RFC 8555, section 8.4), so that gdu can be used as a hook by ACME
+// clients such as certbot, lego or acme.sh
+
+const CHALLENGE_PREFIX: &'static str = "_acme-challenge";
+
+/// Short TTL, challenge records only live during the validation
+const CHALLENGE_TTL: u32 = 300;
+
+#[derive(Debug, PartialEq)]
+pub enum AcmeAction {
+    Present,
+    Cleanup,
+}
+
+impl FromStr for AcmeAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<AcmeAction, String> {
+        match s {
+            "present" => Ok(AcmeAction::Present),
+            "cleanup" => Ok(AcmeAction::Cleanup),
+            value => Err(format!("Unknown value for ACME action: {}", value)),
+        }
+    }
+}
+
+/// Value of the TXT record: base64url encoded SHA-256 digest of the key authorization
+pub fn challenge_value(key_authorization: &str) -> String {
+    hash(Type::SHA256, key_authorization.as_bytes()).to_base64(URL_SAFE)
+}
+
+/// Name of the TXT record relative to the zone of `domain`, for the validated `fqdn`.
+///
+/// `fqdn` may be a wildcard name, or already be the challenge name as given by some clients.
+pub fn challenge_name(fqdn: &str, domain: &str) -> Result<String> {
+    let fqdn = fqdn.trim_right_matches('.').to_lowercase();
+    let domain = domain.trim_right_matches('.').to_lowercase();
+
+    let mut name = fqdn.trim_left_matches("*.");
+    if name.starts_with(&format!("{}.", CHALLENGE_PREFIX)) {
+        name = &name[CHALLENGE_PREFIX.len() + 1..];
+    }
+
+    if name == domain {
+        return Ok(CHALLENGE_PREFIX.to_string());
+    }
+
+    let suffix = format!(".{}", domain);
+    if name.ends_with(&suffix) {
+        Ok(format!("{}.{}", CHALLENGE_PREFIX, &name[..name.len() - suffix.len()]))
+    } else {
+        Err(Error::InvalidRecord(format!("'{}' is not in the zone of '{}'", fqdn, domain)))
+    }
+}
+
+/// Adds the challenge value to the TXT records of `name`, other values are kept since an ACME
+/// client may validate several names sharing the same challenge record, e.g. `*.domain.com`
+/// and `domain.com`.
+pub fn present_changes<'a>(name: &'a str,
+                           current: &[ZoneRecord],
+                           value: &str)
+                           -> Result<ChangeSet<'a>> {
+    let mut changes = ChangeSet::new();
+
+    if !try!(challenge_values(name, current)).iter().any(|&(_, ref txt)| txt == value) {
+        changes.create(challenge_record(name, CHALLENGE_TTL),
+                       RecordValue::Txt(vec![value.to_string()]));
+    }
+
+    Ok(changes)
+}
+
+/// Removes the challenge value from the TXT records of `name`, by deleting them and creating
+/// the other values again in the same zone version
+pub fn cleanup_changes<'a>(name: &'a str,
+                           current: &[ZoneRecord],
+                           value: &str)
+                           -> Result<ChangeSet<'a>> {
+    let mut changes = ChangeSet::new();
+    let values = try!(challenge_values(name, current));
+
+    if values.iter().any(|&(_, ref txt)| txt == value) {
+        changes.delete(Record::of_type(name, RecordType::TXT));
+
+        for &(ttl, ref txt) in values.iter().filter(|&&(_, ref txt)| txt != value) {
+            changes.create(challenge_record(name, ttl),
+                           RecordValue::Txt(vec![txt.clone()]));
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Queries the authoritative nameservers of `domain` until all of them serve the challenge
+/// value, `resolver` is used to look them up
+pub fn wait_for_propagation(domain: &str,
+                            name: &str,
+                            value: &str,
+                            resolver: &Resolver,
+                            timeout: Duration)
+                            -> Result<Vec<NameserverStatus>> {
+    let expected = Answer::TXT(vec![value.to_string()]);
+
+    propagation::check(resolver,
+                       domain,
+                       name,
+                       QueryType::TXT,
+                       |answers| answers.contains(&expected),
+                       timeout)
+}
+
+/// TTL and value of each single-string TXT record of `name`
+fn challenge_values(name: &str, current: &[ZoneRecord]) -> Result<Vec<(u32, String)>> {
+    let mut values = Vec::new();
+
+    for record in current.iter()
+        .filter(|record| record.name == name && record.type_.to_uppercase() == "TXT") {
+        if let RecordValue::Txt(strings) = try!(RecordValue::parse(&RecordType::TXT,
+                                                                   &record.value)) {
+            values.push((record.ttl, strings.concat()));
+        }
+    }
+
+    Ok(values)
+}
+
+fn challenge_record(name: &str, ttl: u32) -> Record {
+    let mut record = Record::of_type(name, RecordType::TXT);
+    record.ttl = Some(ttl);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::ZoneRecord;
+
+    fn txt_record(name: &str, value: &str) -> ZoneRecord {
+        ZoneRecord {
+            name: name.to_string(),
+            type_: "TXT".to_string(),
+            ttl: 300,
+            value: value.to_string(),
+        }
+    }
+
+    fn change_lines(changes: ChangeSet) -> Vec<String> {
+        changes.iter().map(|change| change.to_string()).collect()
+    }
+
+    #[test]
+    fn key_authorization_digest() {
+        assert_eq!("ungWv48Bz-pBQUDeXa4iI7ADYaOWF3qctBD_YfIAFa0",
+                   challenge_value("abc"));
+        assert_eq!("NGwKoXBgCT8JhEa0bK7AwfSqHyu_ZWeugV07fLGIVq0",
+                   challenge_value("evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA.\
+                                    nP1qzpXGymHBrUEepNY9HCsQk7K8KhOypzEt62jcerQ"));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!("_acme-challenge",
+                   challenge_name("example.com", "example.com").unwrap());
+        assert_eq!("_acme-challenge",
+                   challenge_name("*.Example.com.", "example.com").unwrap());
+        assert_eq!("_acme-challenge.www",
+                   challenge_name("www.example.com", "example.com").unwrap());
+        assert_eq!("_acme-challenge.www",
+                   challenge_name("_acme-challenge.www.example.com.", "example.com").unwrap());
+        assert!(challenge_name("www.example.org", "example.com").is_err());
+    }
+
+    #[test]
+    fn present() {
+        let current = vec![txt_record("_acme-challenge", "other")];
+
+        assert_eq!(vec!["+ _acme-challenge 300 TXT \"digest\"".to_string()],
+                   change_lines(present_changes("_acme-challenge", &current, "digest").unwrap()));
+    }
+
+    #[test]
+    fn already_present() {
+        let current = vec![txt_record("_acme-challenge", "\"digest\"")];
+
+        assert!(present_changes("_acme-challenge", &current, "digest").unwrap().is_empty());
+    }
+
+    #[test]
+    fn cleanup_keeps_other_values() {
+        let current = vec![txt_record("_acme-challenge", "other"),
+                           txt_record("_acme-challenge", "digest"),
+                           txt_record("www", "digest")];
+
+        assert_eq!(vec!["- _acme-challenge TXT".to_string(),
+                        "+ _acme-challenge 300 TXT \"other\"".to_string()],
+                   change_lines(cleanup_changes("_acme-challenge", &current, "digest").unwrap()));
+    }
+
+    #[test]
+    fn cleanup_nothing_to_do() {
+        let current = vec![txt_record("_acme-challenge", "other")];
+
+        assert!(cleanup_changes("_acme-challenge", &current, "digest").unwrap().is_empty());
+    }
+}
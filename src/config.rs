@@ -1,9 +1,131 @@
-use myip::IpProvider;
+use acme::AcmeAction;
+use dns::{ProviderType, RecordType};
+use export::ExportFormat;
+use http::HttpSettings;
+use myip::IpDetector;
+use prefix::HostSuffix;
+use profiles::ManagedRecord;
+use std::collections::BTreeMap;
+use std::net::{Ipv6Addr, SocketAddr};
+use toml;
 
+/// DNS provider configuration
+#[derive(Clone)]
 pub struct Config {
-    pub apikey: String,
+    pub provider: ProviderType,
+    /// Settings of the provider, e.g. the source of the API key, decoded when it is built
+    pub settings: toml::Table,
+    pub http: HttpSettings,
     pub domain: String,
+    pub dry_run: bool,
+    pub keep_versions: Option<u16>,
+}
+
+pub enum Command {
+    /// Print the current IP address
+    Detect(IpDetector),
+    /// List the providers compiled in
+    Providers,
+    /// Compare the record with the current IP address
+    Status(Config, StatusOptions),
+    /// List all the records of the zone
+    List(Config),
+    /// Export all the records of the zone
+    Export(Config, ExportFormat),
+    /// Synchronize the zone with a zonefile
+    Sync(Config, String),
+    /// Create or update the record with the current IP address
+    Update(Config, UpdateOptions),
+    /// Create or update all the records of the config file with the current IP address
+    UpdateAll(ManagedRecords, UpdateOptions),
+    /// Create or update the AAAA records of LAN hosts from the delegated IPv6 prefix
+    Prefix(Config, PrefixOptions),
+    /// Create or update the record with the given value
+    Set(Config, SetOptions),
+    /// Delete the record
+    Delete(Config, DeleteOptions),
+    /// Create or delete an ACME DNS-01 challenge record
+    Acme(Config, AcmeOptions),
+}
+
+/// Records of the config file, each one updated with the credentials of its profile
+pub struct ManagedRecords {
+    /// DNS provider configuration of each profile, without domain
+    pub profiles: BTreeMap<String, Config>,
+    pub records: Vec<ManagedRecord>,
+}
+
+pub struct StatusOptions {
     pub record_name: String,
+    pub ip_detector: IpDetector,
+}
+
+#[derive(Clone)]
+pub struct UpdateOptions {
+    pub record_name: String,
+    pub ip_detector: IpDetector,
+    pub ttl: Option<u32>,
     pub force: bool,
-    pub ip_provider: IpProvider,
+    /// Check that the authoritative nameservers serve the record once updated
+    pub verify: Option<PropagationOptions>,
+    /// Exit with a distinct code when the record is updated or created
+    pub detailed_exit_codes: bool,
+    /// Shell command run once the record is updated or created
+    pub on_change: Option<String>,
+    /// URL notified once the record is updated or created
+    pub webhook: Option<String>,
+    /// Run continuously instead of once
+    pub daemon: Option<DaemonOptions>,
+}
+
+#[derive(Clone)]
+pub struct DaemonOptions {
+    /// Delay between two updates, in seconds
+    pub interval: u64,
+    /// Address of the Prometheus metrics endpoint
+    pub metrics_listen: Option<SocketAddr>,
+}
+
+/// Where the delegated IPv6 prefix comes from
+pub enum PrefixSource {
+    /// Prefix of the current IPv6 address
+    Detect(IpDetector),
+    /// Prefix of the given IPv6 address, e.g. of a LAN interface
+    Address(Ipv6Addr),
+}
+
+pub struct PrefixOptions {
+    pub source: PrefixSource,
+    pub prefix_length: u8,
+    pub hosts: Vec<HostSuffix>,
+    pub ttl: Option<u32>,
+}
+
+pub struct SetOptions {
+    pub record_name: String,
+    pub record_type: RecordType,
+    pub value: String,
+    pub ttl: Option<u32>,
+}
+
+pub struct DeleteOptions {
+    pub record_name: String,
+    pub record_type: RecordType,
+}
+
+pub struct AcmeOptions {
+    pub action: AcmeAction,
+    pub fqdn: String,
+    pub key_authorization: String,
+    /// `key_authorization` is already the value of the TXT record
+    pub digest: bool,
+    pub propagation: PropagationOptions,
+}
+
+#[derive(Clone)]
+pub struct PropagationOptions {
+    /// Recursive resolver used to find the authoritative nameservers
+    pub resolver: Option<SocketAddr>,
+    /// Maximum time to wait for the nameservers to serve the record, in seconds
+    pub timeout: u64,
 }
@@ -1,50 +1,184 @@
 use config::Config;
+use error::Error;
 use error::Result;
+use std::fmt;
 use std::net::IpAddr;
+use gandi;
 use gandi::GandiRPC;
 use gandi::GandiRpcEndpoint;
 use gandi::ZoneVersion;
+use http::HttpSettings;
+use metrics::TimedDNSProvider;
+use registry::{Registration, Registry};
+use secret::{self, Secret, SecretSource};
+use std::result::Result as StdResult;
 use std::str::FromStr;
+use toml;
+
+/// Constructor of a DNS provider, from the configuration of the command. The provider decodes
+/// its own settings, those of its profile or of the command line.
+type DNSProviderBuild = for<'a> fn(&'a Config) -> Result<Box<DNSProvider + 'a>>;
+
+/// DNS providers available
+pub static DNS_PROVIDERS: Registry<DNSProviderBuild> = Registry {
+    kind: "DNS provider",
+    providers: &[Registration {
+                     name: "gandi",
+                     description: "Gandi, with its XML-RPC API",
+                     settings: &[("apikey", "API key"),
+                                 ("apikey_file", "File holding the API key"),
+                                 ("apikey_command", "Shell command printing the API key"),
+                                 ("apikey_env", "Environment variable holding the API key")],
+                     defaults: &[("apikey_env", secret::APIKEY_ENV)],
+                     build: gandi_provider,
+                 }],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProviderType(&'static Registration<DNSProviderBuild>);
+
+impl ProviderType {
+    /// Whether `name` is a setting of the provider in a profile of the config file
+    pub fn has_setting(&self, name: &str) -> bool {
+        self.0.has_setting(name)
+    }
+
+    /// Settings of the provider when the command line sets none, e.g. the environment variable
+    /// holding the API key
+    pub fn default_settings(&self) -> toml::Table {
+        self.0
+            .defaults
+            .iter()
+            .map(|&(setting, value)| (setting.to_string(), toml::Value::String(value.to_string())))
+            .collect()
+    }
+}
+
+impl FromStr for ProviderType {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<ProviderType, String> {
+        DNS_PROVIDERS.find(s).map(ProviderType)
+    }
+}
+
+impl fmt::Display for ProviderType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0.name)
+    }
+}
 
 pub struct DNSProviderFactory;
 
 impl<'a> DNSProviderFactory {
-    pub fn build(config: &'a Config) -> Box<DNSProvider + 'a> {
-        Box::new(GandiDNSProvider::new(&config.apikey))
+    /// Builds the DNS provider of `config`, its calls being timed for the metrics. Fails if
+    /// its HTTP client can't be built, e.g. with an invalid CA file.
+    pub fn build(config: &'a Config) -> Result<Box<DNSProvider + 'a>> {
+        let dns_provider = try!((config.provider.0.build)(config));
+        Ok(Box::new(TimedDNSProvider(dns_provider)))
     }
 }
 
-#[derive(Debug)]
+fn gandi_provider<'a>(config: &'a Config) -> Result<Box<DNSProvider + 'a>> {
+    let apikey_source = try!(SecretSource::from_settings(&config.settings, "apikey"));
+    let apikey = try!(apikey_source.read());
+    debug!("Using apikey from {}: {}", apikey_source, apikey);
+
+    let dns_provider = try!(GandiDNSProvider::new(apikey,
+                                                  &config.http,
+                                                  config.dry_run,
+                                                  config.keep_versions));
+    Ok(Box::new(dns_provider))
+}
+
+#[derive(Debug, Clone)]
 pub struct Record<'a> {
     pub name: &'a str,
     pub type_: RecordType,
+    /// TTL in seconds, provider default if not set
+    pub ttl: Option<u32>,
 }
 
 impl<'a> Record<'a> {
-    pub fn new(record_name: &'a str, ip_addr: &IpAddr) -> Record<'a> {
+    pub fn new(record_name: &'a str, ip_addr: &IpAddr, ttl: Option<u32>) -> Record<'a> {
         let record_type = RecordType::from_ipaddr(&ip_addr);
 
         Record {
             name: record_name,
             type_: record_type,
+            ttl: ttl,
+        }
+    }
+
+    pub fn of_type(record_name: &'a str, record_type: RecordType) -> Record<'a> {
+        Record {
+            name: record_name,
+            type_: record_type,
+            ttl: None,
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum RecordType {
-    A,
-    AAAA,
+/// A record as currently declared by the DNS provider
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeclaredRecord {
+    pub value: RecordValue,
+    pub ttl: u32,
 }
 
-impl RecordType {
-    pub fn to_string(&self) -> String {
+impl DeclaredRecord {
+    /// Returns true if this declaration already matches `record` with `value`.
+    ///
+    /// TTL isn't compared if `record` doesn't define one.
+    pub fn is_up_to_date(&self, record: &Record, value: &RecordValue) -> bool {
+        &self.value == value && record.ttl.map_or(true, |ttl| ttl == self.ttl)
+    }
+}
+
+/// Outcome of the update of a record with the current IP address
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Already up to date
+    Unchanged(IpAddr),
+    /// Declared with the `previous` value
+    Updated {
+        previous: RecordValue,
+        ip_addr: IpAddr,
+    },
+    /// Not declared before
+    Created(IpAddr),
+}
+
+impl Outcome {
+    /// IP address the record is now declared with
+    pub fn ip_addr(&self) -> &IpAddr {
+        match self {
+            &Outcome::Unchanged(ref ip_addr) => ip_addr,
+            &Outcome::Updated { ref ip_addr, .. } => ip_addr,
+            &Outcome::Created(ref ip_addr) => ip_addr,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
         match self {
-            &RecordType::A => "A".to_string(),
-            &RecordType::AAAA => "AAAA".to_string(),
+            &Outcome::Unchanged(_) => "unchanged",
+            &Outcome::Updated { .. } => "updated",
+            &Outcome::Created(_) => "created",
         }
     }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordType {
+    A,
+    AAAA,
+    CNAME,
+    MX,
+    SRV,
+    TXT,
+}
 
+impl RecordType {
     pub fn from_ipaddr(ip_addr: &IpAddr) -> RecordType {
         match ip_addr {
             &IpAddr::V4(_) => RecordType::A,
@@ -53,35 +187,330 @@ impl RecordType {
     }
 }
 
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            &RecordType::A => "A",
+            &RecordType::AAAA => "AAAA",
+            &RecordType::CNAME => "CNAME",
+            &RecordType::MX => "MX",
+            &RecordType::SRV => "SRV",
+            &RecordType::TXT => "TXT",
+        })
+    }
+}
+
+impl FromStr for RecordType {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<RecordType, String> {
+        match s.to_uppercase().as_ref() {
+            "A" => Ok(RecordType::A),
+            "AAAA" => Ok(RecordType::AAAA),
+            "CNAME" => Ok(RecordType::CNAME),
+            "MX" => Ok(RecordType::MX),
+            "SRV" => Ok(RecordType::SRV),
+            "TXT" => Ok(RecordType::TXT),
+            _ => Err(format!("Unknown value for record type: {}", s)),
+        }
+    }
+}
+
+/// Value of a record, its format depends on the record type
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordValue {
+    /// A or AAAA
+    Ip(IpAddr),
+    /// Target of a CNAME
+    Name(String),
+    Mx {
+        preference: u16,
+        exchange: String,
+    },
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    /// Character-strings of a TXT, 255 bytes at most each
+    Txt(Vec<String>),
+}
+
+impl RecordValue {
+    /// Parses and validates `value` as presented in zonefiles, for a record of type `record_type`.
+    ///
+    /// TXT values can be a sequence of quoted character-strings or raw text, which is split in
+    /// character-strings if needed.
+    pub fn parse(record_type: &RecordType, value: &str) -> Result<RecordValue> {
+        let value = value.trim();
+        let fields = value.split_whitespace().collect::<Vec<&str>>();
+
+        match record_type {
+            &RecordType::A | &RecordType::AAAA => {
+                let ip_addr = try!(IpAddr::from_str(value));
+                if &RecordType::from_ipaddr(&ip_addr) != record_type {
+                    return Err(invalid_value(record_type, value));
+                }
+                Ok(RecordValue::Ip(ip_addr))
+            }
+            &RecordType::CNAME => Ok(RecordValue::Name(try!(parse_name(record_type, value)))),
+            &RecordType::MX if fields.len() == 2 => {
+                Ok(RecordValue::Mx {
+                    preference: try!(parse_u16(record_type, fields[0])),
+                    exchange: try!(parse_target(record_type, fields[1])),
+                })
+            }
+            &RecordType::SRV if fields.len() == 4 => {
+                Ok(RecordValue::Srv {
+                    priority: try!(parse_u16(record_type, fields[0])),
+                    weight: try!(parse_u16(record_type, fields[1])),
+                    port: try!(parse_u16(record_type, fields[2])),
+                    target: try!(parse_target(record_type, fields[3])),
+                })
+            }
+            &RecordType::TXT => Ok(RecordValue::Txt(try!(parse_txt(value)))),
+            _ => Err(invalid_value(record_type, value)),
+        }
+    }
+}
+
+impl fmt::Display for RecordValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &RecordValue::Ip(ref ip_addr) => write!(f, "{}", ip_addr),
+            &RecordValue::Name(ref name) => f.write_str(name),
+            &RecordValue::Mx { preference, ref exchange } => {
+                write!(f, "{} {}", preference, exchange)
+            }
+            &RecordValue::Srv { priority, weight, port, ref target } => {
+                write!(f, "{} {} {} {}", priority, weight, port, target)
+            }
+            &RecordValue::Txt(ref strings) => {
+                let quoted = strings.iter()
+                    .map(|string| string.replace("\\", "\\\\").replace("\"", "\\\""))
+                    .map(|string| format!("\"{}\"", string))
+                    .collect::<Vec<String>>();
+                f.write_str(&quoted.join(" "))
+            }
+        }
+    }
+}
+
+fn invalid_value(record_type: &RecordType, value: &str) -> Error {
+    Error::InvalidRecord(format!("'{}' isn't a valid {} value", value, record_type))
+}
+
+fn parse_u16(record_type: &RecordType, value: &str) -> Result<u16> {
+    value.parse::<u16>().map_err(|_| invalid_value(record_type, value))
+}
+
+/// Validates the host name of a MX or SRV record, or "." for no host: null MX (RFC 7505) or
+/// service not available (RFC 2782)
+fn parse_target(record_type: &RecordType, value: &str) -> Result<String> {
+    match value {
+        "." => Ok(value.to_string()),
+        _ => parse_name(record_type, value),
+    }
+}
+
+/// Validates a host name, either absolute (ending with a dot) or relative to the zone
+fn parse_name(record_type: &RecordType, value: &str) -> Result<String> {
+    let name = value.to_lowercase();
+    let labels = name.trim_right_matches('.').split('.').collect::<Vec<&str>>();
+
+    let is_valid = name.len() <= 254 && name != "." &&
+                   labels.iter().all(|label| {
+        !label.is_empty() && label.len() <= 63 &&
+        label.chars().all(|c| match c {
+            'a'...'z' | '0'...'9' | '-' | '_' => true,
+            _ => false,
+        })
+    });
+
+    match is_valid {
+        true => Ok(name),
+        false => Err(invalid_value(record_type, value)),
+    }
+}
+
+fn parse_txt(value: &str) -> Result<Vec<String>> {
+    if !value.starts_with('"') {
+        // Raw text, split in character-strings of 255 bytes at most
+        let mut strings = Vec::new();
+        let mut current = String::new();
+        for c in value.chars() {
+            if current.len() + c.len_utf8() > 255 {
+                strings.push(current);
+                current = String::new();
+            }
+            current.push(c);
+        }
+        strings.push(current);
+        return Ok(strings);
+    }
+
+    let mut strings = Vec::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut string = String::new();
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            if let Some(escaped) = chars.next() {
+                                string.push(escaped);
+                            }
+                        }
+                        '"' => {
+                            closed = true;
+                            break;
+                        }
+                        _ => string.push(c),
+                    }
+                }
+                if !closed || string.len() > 255 {
+                    return Err(invalid_value(&RecordType::TXT, value));
+                }
+                strings.push(string);
+            }
+            c if c.is_whitespace() => (),
+            _ => return Err(invalid_value(&RecordType::TXT, value)),
+        }
+    }
+
+    Ok(strings)
+}
+
+/// Any record of a zone, whatever its type
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneRecord {
+    pub name: String,
+    pub type_: String,
+    pub ttl: u32,
+    pub value: String,
+}
+
+#[derive(Debug)]
+pub enum Change<'a> {
+    Create(Record<'a>, RecordValue),
+    Update(Record<'a>, RecordValue),
+    Delete(Record<'a>),
+}
+
+impl<'a> fmt::Display for Change<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (sign, record, maybe_value) = match self {
+            &Change::Create(ref record, ref value) => ("+", record, Some(value)),
+            &Change::Update(ref record, ref value) => ("~", record, Some(value)),
+            &Change::Delete(ref record) => ("-", record, None),
+        };
+
+        try!(write!(f, "{} {}", sign, record.name));
+        if let Some(ttl) = record.ttl {
+            try!(write!(f, " {}", ttl));
+        }
+        try!(write!(f, " {}", record.type_));
+        if let Some(value) = maybe_value {
+            try!(write!(f, " {}", value));
+        }
+        Ok(())
+    }
+}
+
+/// Changes to apply all at once to a zone, with `DNSProvider::commit`
+#[derive(Debug)]
+pub struct ChangeSet<'a> {
+    changes: Vec<Change<'a>>,
+}
+
+impl<'a> ChangeSet<'a> {
+    pub fn new() -> ChangeSet<'a> {
+        ChangeSet { changes: Vec::new() }
+    }
+
+    pub fn create(&mut self, record: Record<'a>, value: RecordValue) {
+        self.changes.push(Change::Create(record, value));
+    }
+
+    pub fn update(&mut self, record: Record<'a>, value: RecordValue) {
+        self.changes.push(Change::Update(record, value));
+    }
+
+    pub fn delete(&mut self, record: Record<'a>) {
+        self.changes.push(Change::Delete(record));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<Change<'a>> {
+        self.changes.iter()
+    }
+}
+
 pub trait DNSProvider {
     fn init(&mut self, domain: &str) -> Result<()>;
     fn handle_ipv6_addr(&self) -> bool;
-    fn is_record_already_declared(&self, record: &Record) -> Result<Option<IpAddr>>;
-    fn update_record(&self, record: &Record, ip_addr: &IpAddr) -> Result<()>;
-    fn create_record(&self, record: &Record, ip_addr: &IpAddr) -> Result<()>;
+    fn is_record_already_declared(&self, record: &Record) -> Result<Option<DeclaredRecord>>;
+    fn list_records(&self) -> Result<Vec<ZoneRecord>>;
+
+    /// Applies all the changes to the zone, or none of them.
+    ///
+    /// Returns the zone version created and activated, for providers versioning zones.
+    fn commit(&self, changes: &ChangeSet) -> Result<Option<u16>>;
+
+    fn update_record(&self, record: &Record, value: &RecordValue) -> Result<Option<u16>> {
+        let mut changes = ChangeSet::new();
+        changes.update(record.clone(), value.clone());
+        self.commit(&changes)
+    }
+
+    fn create_record(&self, record: &Record, value: &RecordValue) -> Result<Option<u16>> {
+        let mut changes = ChangeSet::new();
+        changes.create(record.clone(), value.clone());
+        self.commit(&changes)
+    }
+
+    fn delete_record(&self, record: &Record) -> Result<Option<u16>> {
+        let mut changes = ChangeSet::new();
+        changes.delete(record.clone());
+        self.commit(&changes)
+    }
 }
 
-pub struct GandiDNSProvider<'a> {
+pub struct GandiDNSProvider {
     zone_id: u32,
-    gandi_rpc: GandiRPC<'a>,
+    gandi_rpc: GandiRPC,
+    dry_run: bool,
+    keep_versions: Option<u16>,
 }
 
-impl<'a> GandiDNSProvider<'a> {
-    pub fn new(gandi_apikey: &'a str) -> GandiDNSProvider<'a> {
+impl GandiDNSProvider {
+    pub fn new(gandi_apikey: Secret,
+               http: &HttpSettings,
+               dry_run: bool,
+               keep_versions: Option<u16>)
+               -> Result<GandiDNSProvider> {
 
-        let gandi_rpc = GandiRPC::new(GandiRpcEndpoint::PROD, gandi_apikey);
+        let gandi_rpc = try!(GandiRPC::new(GandiRpcEndpoint::PROD, gandi_apikey, http));
 
-        GandiDNSProvider {
+        Ok(GandiDNSProvider {
             zone_id: Default::default(),
             gandi_rpc: gandi_rpc,
-        }
+            dry_run: dry_run,
+            keep_versions: keep_versions,
+        })
     }
 }
 
-impl<'a> DNSProvider for GandiDNSProvider<'a> {
+impl DNSProvider for GandiDNSProvider {
     fn init(&mut self, domain: &str) -> Result<()> {
 
-        let response = &self.gandi_rpc.domain_info(domain);
+        let response = &try!(self.gandi_rpc.domain_info(domain));
 
         // TODO: fix this ugly code
         // Handle errors
@@ -102,74 +531,368 @@ impl<'a> DNSProvider for GandiDNSProvider<'a> {
         true
     }
 
-    fn is_record_already_declared(&self, record: &Record) -> Result<Option<IpAddr>> {
-        let zone = &self.gandi_rpc.domain_zone_record_list(&record.name,
-                                                           &record.type_.to_string(),
-                                                           &self.zone_id,
-                                                           ZoneVersion::LATEST);
+    fn is_record_already_declared(&self, record: &Record) -> Result<Option<DeclaredRecord>> {
+        let zone = try!(self.gandi_rpc.domain_zone_record_list(&record.name,
+                                                               &record.type_.to_string(),
+                                                               &self.zone_id,
+                                                               ZoneVersion::LATEST));
 
-        match zone.clone() {
+        match zone.into_iter().next() {
             None => Ok(None),
-            Some(zone) => Ok(Some(try!(IpAddr::from_str(&zone.ip_addr)))),
+            Some(zone) => {
+                Ok(Some(DeclaredRecord {
+                    value: try!(RecordValue::parse(&record.type_, &zone.value)),
+                    ttl: zone.ttl,
+                }))
+            }
         }
     }
 
-    fn update_record(&self, record: &Record, ip_addr: &IpAddr) -> Result<()> {
+    fn list_records(&self) -> Result<Vec<ZoneRecord>> {
+        let records = try!(self.gandi_rpc
+            .domain_zone_record_list_all(&self.zone_id, ZoneVersion::LATEST));
+
+        Ok(records.into_iter()
+            .map(|record| {
+                ZoneRecord {
+                    name: record.name,
+                    type_: record.type_,
+                    ttl: record.ttl,
+                    value: record.value,
+                }
+            })
+            .collect())
+    }
 
-        // Create a new zone and get returned version
+    fn commit(&self, changes: &ChangeSet) -> Result<Option<u16>> {
+        if changes.is_empty() {
+            debug!("No change to apply to the zone '{}'", &self.zone_id);
+            return Ok(None);
+        }
 
-        let new_zone_version = &self.gandi_rpc.domain_zone_version_new(&self.zone_id);
+        self.in_new_zone_version(|new_zone_version| {
+            for change in changes.iter() {
+                try!(self.apply_change(change, new_zone_version));
+            }
+            Ok(())
+        })
+    }
+}
+
+impl GandiDNSProvider {
+    fn apply_change(&self, change: &Change, new_zone_version: u16) -> Result<()> {
+        debug!("Apply change to version '{}' of the zone '{}': {:?}",
+               new_zone_version,
+               &self.zone_id,
+               change);
+
+        match change {
+            &Change::Create(ref record, ref value) => {
+                // Create the new record for the new zone version
+                let ttl = record.ttl.unwrap_or(gandi::DEFAULT_TTL);
+
+                self.gandi_rpc.domain_zone_record_add(&record.name,
+                                                      &record.type_.to_string(),
+                                                      &value.to_string(),
+                                                      &ttl,
+                                                      &self.zone_id,
+                                                      &new_zone_version)
+            }
+            &Change::Update(ref record, ref value) => {
+                let mut zones = try!(self.gandi_rpc
+                    .domain_zone_record_list(&record.name,
+                                             &record.type_.to_string(),
+                                             &self.zone_id,
+                                             ZoneVersion::ANOTHER(new_zone_version)));
+
+                // Which value of a set to replace is unknown, sets are deleted and created again
+                let zone = match zones.len() {
+                    0 => {
+                        return Err(Error::XmlRpc(format!("Record '{}' not found in new zone \
+                                                          version",
+                                                         record.name)))
+                    }
+                    1 => zones.remove(0),
+                    values => {
+                        return Err(Error::InvalidRecord(format!("Record '{}' has {} {} values, \
+                                                                 only a record with a single \
+                                                                 value can be updated",
+                                                                record.name,
+                                                                values,
+                                                                record.type_)))
+                    }
+                };
+
+                debug!("New zone: {:?}", zone);
+
+                // Keep current TTL if none is required
+                let ttl = record.ttl.unwrap_or(zone.ttl);
+
+                // Update zone with the new record
+                self.gandi_rpc.domain_zone_record_update(&record.name,
+                                                         &record.type_.to_string(),
+                                                         &value.to_string(),
+                                                         &ttl,
+                                                         &self.zone_id,
+                                                         &new_zone_version,
+                                                         &zone.record_id)
+            }
+            &Change::Delete(ref record) => {
+                let deleted = try!(self.gandi_rpc
+                    .domain_zone_record_delete(&record.name,
+                                               &record.type_.to_string(),
+                                               &self.zone_id,
+                                               &new_zone_version));
+
+                match deleted {
+                    0 => {
+                        Err(Error::XmlRpc(format!("Record '{}' not found in new zone version",
+                                                  record.name)))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Creates a new version of the zone, applies `edit` to it and activates it.
+    ///
+    /// Each step is checked: if `edit` fails or if the new version can't be activated, the new
+    /// version is deleted, so the active zone is left untouched and no orphan version remains.
+    fn in_new_zone_version<F>(&self, edit: F) -> Result<Option<u16>>
+        where F: Fn(u16) -> Result<()>
+    {
+        // No call changing the zone, nor listing its versions to prune them
+        if self.dry_run {
+            info!("Dry run, zone '{}' not updated and its old versions not deleted",
+                  &self.zone_id);
+            return Ok(None);
+        }
+
+        // Create a new zone and get returned version
+        let new_zone_version = try!(self.gandi_rpc.domain_zone_version_new(&self.zone_id));
 
         debug!("New zone version: {}", new_zone_version);
 
-        let zone = &self.gandi_rpc
-            .domain_zone_record_list(&record.name,
-                                     &record.type_.to_string(),
-                                     &self.zone_id,
-                                     ZoneVersion::ANOTHER(*new_zone_version))
-            .unwrap();
-
-        debug!("New zone: {:?}", zone);
-
-        // Update zone with the new record
-        &self.gandi_rpc.domain_zone_record_update(&record.name,
-                                                  &record.type_.to_string(),
-                                                  ip_addr,
-                                                  &self.zone_id,
-                                                  new_zone_version,
-                                                  &zone.record_id);
-
-        // Activate the new zone
-        debug!("Activate version '{}' of the zone '{}'",
-               new_zone_version,
-               &self.zone_id);
+        let result = edit(new_zone_version).and_then(|_| {
+            // Activate the new zone
+            debug!("Activate version '{}' of the zone '{}'",
+                   new_zone_version,
+                   &self.zone_id);
+
+            match try!(self.gandi_rpc.domain_zone_version_set(&self.zone_id, &new_zone_version)) {
+                true => Ok(()),
+                false => {
+                    Err(Error::XmlRpc(format!("Unable to activate version '{}' of the zone '{}'",
+                                              new_zone_version,
+                                              self.zone_id)))
+                }
+            }
+        });
+
+        if let Err(ref err) = result {
+            warn!("Zone update failed ({}), delete version '{}' of the zone '{}'",
+                  err,
+                  new_zone_version,
+                  &self.zone_id);
+
+            match self.gandi_rpc.domain_zone_version_delete(&self.zone_id, &new_zone_version) {
+                Ok(true) => (),
+                Ok(false) => {
+                    error!("Version '{}' of the zone '{}' hasn't been deleted",
+                           new_zone_version,
+                           &self.zone_id)
+                }
+                Err(delete_err) => {
+                    error!("Unable to delete version '{}' of the zone '{}': {}",
+                           new_zone_version,
+                           &self.zone_id,
+                           delete_err)
+                }
+            }
+        }
+
+        try!(result);
+
+        // The record is updated at this point, a failed cleanup mustn't fail the whole process
+        if let Err(err) = self.prune_zone_versions() {
+            warn!("Unable to delete old versions of the zone '{}': {}",
+                  &self.zone_id,
+                  err);
+        }
+
+        Ok(Some(new_zone_version))
+    }
+
+    /// Deletes inactive versions of the zone, except the `keep_versions` most recent ones.
+    fn prune_zone_versions(&self) -> Result<()> {
+        let keep_versions = match self.keep_versions {
+            Some(keep_versions) => keep_versions as usize,
+            None => return Ok(()),
+        };
+
+        let active_version = try!(self.gandi_rpc.domain_zone_info(&self.zone_id));
+        let versions = try!(self.gandi_rpc.domain_zone_version_list(&self.zone_id));
+
+        for version in obsolete_zone_versions(versions, active_version, keep_versions) {
+            info!("Delete old version '{}' of the zone '{}'",
+                  version,
+                  &self.zone_id);
+
+            if !try!(self.gandi_rpc.domain_zone_version_delete(&self.zone_id, &version)) {
+                warn!("Version '{}' of the zone '{}' hasn't been deleted",
+                      version,
+                      &self.zone_id);
+            }
+        }
 
-        self.gandi_rpc.domain_zone_version_set(&self.zone_id, &new_zone_version);
-        // TODO: check previous result
         Ok(())
     }
+}
 
-    fn create_record(&self, record: &Record, ip_addr: &IpAddr) -> Result<()> {
-        // Create a new zone and get returned version
+/// Returns inactive versions beyond the `keep_versions` most recent ones.
+fn obsolete_zone_versions(mut versions: Vec<u16>,
+                          active_version: u16,
+                          keep_versions: usize)
+                          -> Vec<u16> {
+    // Most recent versions first
+    versions.sort_by(|a, b| b.cmp(a));
+
+    versions.into_iter()
+        .filter(|version| *version != active_version)
+        .skip(keep_versions)
+        .collect()
+}
 
-        let new_zone_version = &self.gandi_rpc.domain_zone_version_new(&self.zone_id);
+#[cfg(test)]
+mod tests {
+    use super::DeclaredRecord;
+    use super::ProviderType;
+    use super::Record;
+    use super::RecordType;
+    use super::RecordValue;
+    use super::obsolete_zone_versions;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+    use toml;
+
+    #[test]
+    fn up_to_date_record() {
+        let ip_addr = IpAddr::from_str("100.3.5.4").unwrap();
+        let value = RecordValue::Ip(ip_addr);
+        let declared = DeclaredRecord {
+            value: value.clone(),
+            ttl: 300,
+        };
+
+        assert!(declared.is_up_to_date(&Record::new("home", &ip_addr, None), &value));
+        assert!(declared.is_up_to_date(&Record::new("home", &ip_addr, Some(300)), &value));
+    }
 
-        debug!("New zone version: {}", new_zone_version);
+    #[test]
+    fn outdated_record() {
+        let ip_addr = IpAddr::from_str("100.3.5.4").unwrap();
+        let new_ip_addr = IpAddr::from_str("100.3.5.5").unwrap();
+        let declared = DeclaredRecord {
+            value: RecordValue::Ip(ip_addr),
+            ttl: 10800,
+        };
+
+        assert!(!declared.is_up_to_date(&Record::new("home", &new_ip_addr, None),
+                                         &RecordValue::Ip(new_ip_addr)));
+        assert!(!declared.is_up_to_date(&Record::new("home", &ip_addr, Some(300)),
+                                         &RecordValue::Ip(ip_addr)));
+    }
 
-        // Create the new record for the new zone version
-        &self.gandi_rpc.domain_zone_record_add(&record.name,
-                                               &record.type_.to_string(),
-                                               ip_addr,
-                                               &self.zone_id,
-                                               new_zone_version);
+    #[test]
+    fn record_type_from_str() {
+        assert_eq!(RecordType::AAAA, RecordType::from_str("aaaa").unwrap());
+        assert_eq!("CNAME", RecordType::from_str("CNAME").unwrap().to_string());
+        assert!(RecordType::from_str("NS").is_err());
+    }
 
-        // Activate the new zone
-        debug!("Activate version '{}' of the zone '{}'",
-               new_zone_version,
-               &self.zone_id);
+    #[test]
+    fn parse_record_values() {
+        assert_eq!(RecordValue::Ip(IpAddr::from_str("2a01:ca07::3117").unwrap()),
+                   RecordValue::parse(&RecordType::AAAA, "2a01:ca07::3117").unwrap());
+        assert_eq!(RecordValue::Name("home.example.com.".to_string()),
+                   RecordValue::parse(&RecordType::CNAME, "Home.Example.com.").unwrap());
+        assert_eq!(RecordValue::Mx {
+                       preference: 10,
+                       exchange: "spool.mail.gandi.net.".to_string(),
+                   },
+                   RecordValue::parse(&RecordType::MX, "10 spool.mail.gandi.net.").unwrap());
+        assert_eq!(RecordValue::Srv {
+                       priority: 0,
+                       weight: 5,
+                       port: 5060,
+                       target: "sip".to_string(),
+                   },
+                   RecordValue::parse(&RecordType::SRV, "0 5 5060 sip").unwrap());
+        // Null MX and service not available
+        assert_eq!(RecordValue::Mx {
+                       preference: 0,
+                       exchange: ".".to_string(),
+                   },
+                   RecordValue::parse(&RecordType::MX, "0 .").unwrap());
+        assert_eq!("0 0 0 .",
+                   RecordValue::parse(&RecordType::SRV, "0 0 0 .").unwrap().to_string());
+        assert_eq!(RecordValue::Txt(vec!["v=spf1 a".to_string(), "?all".to_string()]),
+                   RecordValue::parse(&RecordType::TXT, "\"v=spf1 a\" \"?all\"").unwrap());
+    }
 
-        self.gandi_rpc.domain_zone_version_set(&self.zone_id, &new_zone_version);
-        // TODO: check previous result
-        Ok(())
+    #[test]
+    fn invalid_record_values() {
+        assert!(RecordValue::parse(&RecordType::A, "2a01:ca07::3117").is_err());
+        assert!(RecordValue::parse(&RecordType::CNAME, "not a name").is_err());
+        assert!(RecordValue::parse(&RecordType::CNAME, ".").is_err());
+        assert!(RecordValue::parse(&RecordType::MX, "spool.mail.gandi.net.").is_err());
+        assert!(RecordValue::parse(&RecordType::SRV, "0 5 70000 sip").is_err());
+        assert!(RecordValue::parse(&RecordType::TXT, "\"unterminated").is_err());
+    }
+
+    #[test]
+    fn txt_values() {
+        let raw = RecordValue::parse(&RecordType::TXT, "say \"hi\"").unwrap();
+        assert_eq!("\"say \\\"hi\\\"\"", raw.to_string());
+        assert_eq!(raw, RecordValue::parse(&RecordType::TXT, &raw.to_string()).unwrap());
+
+        let long = ::std::iter::repeat("a").take(300).collect::<String>();
+        match RecordValue::parse(&RecordType::TXT, &long).unwrap() {
+            RecordValue::Txt(strings) => {
+                assert_eq!(vec![255, 45],
+                           strings.iter().map(|s| s.len()).collect::<Vec<usize>>())
+            }
+            _ => panic!("TXT value expected"),
+        }
+    }
+
+    #[test]
+    fn keep_most_recent_inactive_versions() {
+        let versions = vec![1, 4, 2, 5, 3];
+        assert_eq!(vec![2, 1], obsolete_zone_versions(versions, 5, 2));
+    }
+
+    #[test]
+    fn never_delete_active_version() {
+        let versions = vec![1, 2, 3];
+        assert_eq!(vec![3, 1], obsolete_zone_versions(versions, 2, 0));
+    }
+
+    #[test]
+    fn nothing_to_delete() {
+        let versions = vec![1, 2];
+        assert!(obsolete_zone_versions(versions, 2, 5).is_empty());
+    }
+
+    #[test]
+    fn provider_type() {
+        let gandi = "gandi".parse::<ProviderType>().unwrap();
+        assert_eq!("gandi", gandi.to_string());
+        assert!(gandi.has_setting("apikey_file"));
+        assert!(!gandi.has_setting("secret_key"));
+        assert_eq!(Some(&toml::Value::String("GDU_APIKEY".to_string())),
+                   gandi.default_settings().get("apikey_env"));
+        assert!("route53".parse::<ProviderType>().is_err());
     }
 }
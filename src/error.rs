@@ -6,7 +6,9 @@ use std::num::ParseIntError;
 use hyper::error::Error as HyperError;
 use regex::Error as RegexError;
 
-use self::Error::{Io, AddrParse, XmlRpc, Http, Regex, IpNotFound};
+use self::Error::{Io, AddrParse, XmlRpc, Http, Regex, IpNotFound, ZoneFile,
+                  InvalidRecord, Dns, IpDetection, Auth, Hook, Credentials,
+                  Config, HttpStatus, Tls, WrongIpFamily};
 
 /// Result type often returned from methods
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -19,6 +21,50 @@ pub enum Error {
     Http(HyperError),
     Regex(RegexError),
     IpNotFound,
+    ZoneFile(String),
+    InvalidRecord(String),
+    Dns(String),
+    /// Failure of the IP address provider
+    IpDetection(Box<Error>),
+    /// API key rejected by the DNS provider
+    Auth(String),
+    /// Failure of an `--on-change` command or of a `--webhook`
+    Hook(String),
+    /// API key which can't be read
+    Credentials(String),
+    /// Invalid config file
+    Config(String),
+    /// Unexpected HTTP response status
+    HttpStatus(String),
+    /// Invalid TLS settings, e.g. a CA file which can't be loaded
+    Tls(String),
+    /// IP address detected of another family than the requested one
+    WrongIpFamily(String),
+}
+
+impl Error {
+    /// Name of the variant, e.g. "Http"
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Io(_) => "Io",
+            &AddrParse(_) => "AddrParse",
+            &XmlRpc(_) => "XmlRpc",
+            &Http(_) => "Http",
+            &Regex(_) => "Regex",
+            &IpNotFound => "IpNotFound",
+            &ZoneFile(_) => "ZoneFile",
+            &InvalidRecord(_) => "InvalidRecord",
+            &Dns(_) => "Dns",
+            &IpDetection(_) => "IpDetection",
+            &Auth(_) => "Auth",
+            &Hook(_) => "Hook",
+            &Credentials(_) => "Credentials",
+            &Config(_) => "Config",
+            &HttpStatus(_) => "HttpStatus",
+            &Tls(_) => "Tls",
+            &WrongIpFamily(_) => "WrongIpFamily",
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -30,6 +76,17 @@ impl fmt::Display for Error {
             Http(ref err) => err.fmt(f),
             Regex(ref err) => err.fmt(f),
             IpNotFound => write!(f, "IP address not found in input stream."),
+            ZoneFile(ref label) => write!(f, "Invalid zonefile: {}", label),
+            InvalidRecord(ref label) => f.write_str(label),
+            Dns(ref label) => f.write_str(label),
+            IpDetection(ref err) => write!(f, "IP address detection failed: {}", err),
+            Auth(ref label) => write!(f, "Authentication failed: {}", label),
+            Hook(ref label) => f.write_str(label),
+            Credentials(ref label) => f.write_str(label),
+            Config(ref label) => write!(f, "Invalid config file: {}", label),
+            HttpStatus(ref label) => f.write_str(label),
+            Tls(ref label) => f.write_str(label),
+            WrongIpFamily(ref label) => f.write_str(label),
             //            Another => write!(f, "No matching cities with a \
             //                                             population were found."),
         }
@@ -45,6 +102,17 @@ impl StdError for Error {
             Http(ref err) => err.description(),
             Regex(ref err) => err.description(),
             IpNotFound => "Ip not found",
+            ZoneFile(ref err) => err,
+            InvalidRecord(ref err) => err,
+            Dns(ref err) => err,
+            IpDetection(ref err) => err.description(),
+            Auth(ref err) => err,
+            Hook(ref err) => err,
+            Credentials(ref err) => err,
+            Config(ref err) => err,
+            HttpStatus(ref err) => err,
+            Tls(ref err) => err,
+            WrongIpFamily(ref err) => err,
             //            Another => "not found",
         }
     }
@@ -0,0 +1,189 @@
+use dns::ZoneRecord;
+use error::Result;
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum ExportFormat {
+    Bind,
+    Json,
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<ExportFormat, String> {
+        match s {
+            "bind" => Ok(ExportFormat::Bind),
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            value => Err(format!("Unknown value for export format: {}", value)),
+        }
+    }
+}
+
+/// Writes all the records of the zone of `domain` with the given format.
+///
+/// Records are sorted by name, type and value, so that exports of the same zone can be diffed.
+pub fn export<W: Write>(domain: &str,
+                        records: &[ZoneRecord],
+                        format: &ExportFormat,
+                        out: &mut W)
+                        -> Result<()> {
+    let mut records = records.to_vec();
+    records.sort_by(|a, b| {
+        (&a.name, &a.type_, &a.value).cmp(&(&b.name, &b.type_, &b.value))
+    });
+
+    match format {
+        &ExportFormat::Bind => write_bind(domain, &records, out),
+        &ExportFormat::Json => write_json(&records, out),
+        &ExportFormat::Csv => write_csv(&records, out),
+    }
+}
+
+/// RFC 1035 master file, names are relative to `$ORIGIN`
+fn write_bind<W: Write>(domain: &str, records: &[ZoneRecord], out: &mut W) -> Result<()> {
+    try!(writeln!(out, "; Zone {}, exported by gdu", domain));
+    try!(writeln!(out, "$ORIGIN {}.", domain.trim_right_matches('.')));
+
+    for record in records {
+        let value = match record.type_.as_ref() {
+            "TXT" | "SPF" => quote_txt(&record.value),
+            _ => record.value.clone(),
+        };
+
+        try!(writeln!(out,
+                      "{}\t{}\tIN\t{}\t{}",
+                      record.name,
+                      record.ttl,
+                      record.type_,
+                      value));
+    }
+
+    Ok(())
+}
+
+/// TXT values must be quoted character-strings, quote the value if not already done
+fn quote_txt(value: &str) -> String {
+    if value.starts_with('"') {
+        return value.to_string();
+    }
+
+    format!("\"{}\"", value.replace("\\", "\\\\").replace("\"", "\\\""))
+}
+
+fn write_json<W: Write>(records: &[ZoneRecord], out: &mut W) -> Result<()> {
+    let records = records.iter()
+        .map(|record| {
+            let mut object = BTreeMap::new();
+            object.insert("name".to_string(), Json::String(record.name.clone()));
+            object.insert("type".to_string(), Json::String(record.type_.clone()));
+            object.insert("ttl".to_string(), Json::U64(record.ttl as u64));
+            object.insert("value".to_string(), Json::String(record.value.clone()));
+            Json::Object(object)
+        })
+        .collect();
+
+    try!(writeln!(out, "{}", Json::Array(records).pretty()));
+
+    Ok(())
+}
+
+fn write_csv<W: Write>(records: &[ZoneRecord], out: &mut W) -> Result<()> {
+    try!(writeln!(out, "name,type,ttl,value"));
+
+    for record in records {
+        try!(writeln!(out,
+                      "{},{},{},{}",
+                      escape_csv(&record.name),
+                      escape_csv(&record.type_),
+                      record.ttl,
+                      escape_csv(&record.value)));
+    }
+
+    Ok(())
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::ZoneRecord;
+
+    fn records() -> Vec<ZoneRecord> {
+        vec![ZoneRecord {
+                 name: "www".to_string(),
+                 type_: "CNAME".to_string(),
+                 ttl: 10800,
+                 value: "webredir.vip.gandi.net.".to_string(),
+             },
+             ZoneRecord {
+                 name: "@".to_string(),
+                 type_: "TXT".to_string(),
+                 ttl: 300,
+                 value: "v=spf1 include:_mailcust.gandi.net ?all".to_string(),
+             },
+             ZoneRecord {
+                 name: "@".to_string(),
+                 type_: "A".to_string(),
+                 ttl: 300,
+                 value: "100.3.5.4".to_string(),
+             }]
+    }
+
+    fn export_to_string(format: ExportFormat) -> String {
+        let mut out = Vec::new();
+        export("example.com", &records(), &format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn bind_export() {
+        assert_eq!("; Zone example.com, exported by gdu\n\
+                    $ORIGIN example.com.\n\
+                    @\t300\tIN\tA\t100.3.5.4\n\
+                    @\t300\tIN\tTXT\t\"v=spf1 include:_mailcust.gandi.net ?all\"\n\
+                    www\t10800\tIN\tCNAME\twebredir.vip.gandi.net.\n",
+                   export_to_string(ExportFormat::Bind));
+    }
+
+    #[test]
+    fn csv_export() {
+        assert_eq!("name,type,ttl,value\n\
+                    @,A,300,100.3.5.4\n\
+                    @,TXT,300,v=spf1 include:_mailcust.gandi.net ?all\n\
+                    www,CNAME,10800,webredir.vip.gandi.net.\n",
+                   export_to_string(ExportFormat::Csv));
+    }
+
+    #[test]
+    fn csv_escaping() {
+        assert_eq!("\"a,b\"", super::escape_csv("a,b"));
+        assert_eq!("\"say \"\"hi\"\"\"", super::escape_csv("say \"hi\""));
+    }
+
+    #[test]
+    fn json_export() {
+        let json = export_to_string(ExportFormat::Json);
+        assert!(json.contains("\"name\": \"www\""));
+        assert!(json.contains("\"ttl\": 10800"));
+        assert!(json.contains("\"type\": \"CNAME\""));
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert!("yaml".parse::<ExportFormat>().is_err());
+    }
+}
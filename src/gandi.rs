@@ -1,9 +1,19 @@
-use std::net::IpAddr;
+use error::Error;
+use error::Result;
+use http::{HttpSettings, Idempotence};
 use regex::Regex;
+use secret::Secret;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
 use xmlrpc::client::Client as XMLRPCClient;
 use xmlrpc::protocol::Request as XMLRPCRequest;
 use xmlrpc::protocol::Response as XMLRPCResponse;
 
+/// TTL given by Gandi to records created without TTL
+pub const DEFAULT_TTL: u32 = 10800;
+
+/// Calls to the API since the start of the process
+static API_CALLS: AtomicUsize = ATOMIC_USIZE_INIT;
+
 #[derive(Debug)]
 pub enum GandiRpcEndpoint {
     PROD,
@@ -34,49 +44,64 @@ impl ZoneVersion {
     }
 }
 
-#[derive(Debug)]
-pub struct GandiRPC<'a> {
-    xmlrpc_server: &'a str,
-    apikey: &'a str,
+pub struct GandiRPC {
+    apikey: Secret,
+    /// Shared by all the calls, e.g. not to load the CA file for each of them
+    client: XMLRPCClient,
+}
+
+/// A record of a zone version, as returned by `domain.zone.record.list`
+#[derive(Debug, Clone)]
+pub struct RecordInfo {
+    pub id: u32,
+    pub name: String,
+    pub type_: String,
+    pub ttl: u32,
+    pub value: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct Zone {
-    pub ip_addr: String,
+    pub value: String,
     pub record_id: u32,
+    pub ttl: u32,
 }
 
-impl<'a> GandiRPC<'a> {
-    pub fn new(endpoint: GandiRpcEndpoint, apikey: &'a str) -> GandiRPC {
-        GandiRPC {
-            xmlrpc_server: endpoint.url(),
+impl GandiRPC {
+    pub fn new(endpoint: GandiRpcEndpoint,
+               apikey: Secret,
+               http: &HttpSettings)
+               -> Result<GandiRPC> {
+        Ok(GandiRPC {
             apikey: apikey,
-        }
+            client: try!(XMLRPCClient::new(endpoint.url(), http)),
+        })
     }
 
-    fn get_gandi_client(&self, rpc_action: &str) -> (XMLRPCClient, XMLRPCRequest) {
-        let client = XMLRPCClient::new(self.xmlrpc_server);
+    fn get_gandi_client(&self, rpc_action: &str) -> (&XMLRPCClient, XMLRPCRequest) {
         let mut request = XMLRPCRequest::new(rpc_action);
-        request = request.argument(&self.apikey.to_string());
-        (client, request)
+        request = request.secret_argument(&self.apikey);
+        (&self.client, request)
     }
 
-    pub fn domain_info(&self, domain: &str) -> XMLRPCResponse {
+    pub fn domain_info(&self, domain: &str) -> Result<XMLRPCResponse> {
         trace!("domain_info - domain: {:?}", domain);
 
         let (client, mut request) = self.get_gandi_client("domain.info");
         request = request.argument(&domain.to_string());
         request = request.finalize();
 
-        client.remote_call(&request).unwrap()
+        remote_call(&client, &request)
     }
 
+    /// Returns all the records of a zone version with the name and type, e.g. the values of
+    /// a MX set
     pub fn domain_zone_record_list(&self,
                                    record_name: &str,
                                    record_type: &str,
                                    zone_id: &u32,
                                    zone_version: ZoneVersion)
-                                   -> Option<Zone> {
+                                   -> Result<Vec<Zone>> {
 
         trace!("domain_zone_record_list - record_name: {:?} - record_type: {:?} - zone_id: {:?} \
                 - zone_version: {:?}",
@@ -107,34 +132,52 @@ impl<'a> GandiRPC<'a> {
         // Horrible hack, because 'type' is a reserved keyword ...
         request.body = request.body.replace("type_", "type");
 
-        let body = client.remote_call(&request).unwrap().body;
+        let body = try!(remote_call(&client, &request)).body;
 
-        // IP address
-        let regex = Regex::new(r"<value><string>([0-9.]*)</string></value>").unwrap();
+        Ok(zones(&body))
+    }
 
-        let caps = regex.captures(&body);
+    /// Returns all the records of a zone version
+    pub fn domain_zone_record_list_all(&self,
+                                       zone_id: &u32,
+                                       zone_version: ZoneVersion)
+                                       -> Result<Vec<RecordInfo>> {
 
-        let maybe_ip_addr = caps.map_or(None, |caps| caps.at(1));
+        trace!("domain_zone_record_list_all - zone_id: {:?} - zone_version: {:?}",
+               zone_id,
+               zone_version);
 
-        // record_id
-        let regex = Regex::new(r"<int>([0-9]+)</int>").unwrap();
+        let (client, mut request) = self.get_gandi_client("domain.zone.record.list");
+        request = request.argument(zone_id);
+        request = request.argument(&zone_version.to_number());
+        request = request.finalize();
 
-        let caps = regex.captures(&body);
+        let body = try!(remote_call(&client, &request)).body;
 
-        let maybe_record_id = caps.map_or(None, |caps| caps.at(1))
-            .map(|val| val.parse::<u32>().ok().unwrap());
+        let regex = Regex::new(r"(?s)<struct>(.*?)</struct>").unwrap();
 
-        maybe_ip_addr.and_then(|ip| {
-            maybe_record_id.map(|id| {
-                Zone {
-                    ip_addr: ip.to_string(),
-                    record_id: id,
-                }
-            })
-        })
+        let mut records = Vec::new();
+        for caps in regex.captures_iter(&body) {
+            let record = caps.at(1).unwrap();
+
+            let id = try!(member_value(record, "id")
+                .ok_or(unexpected_response("domain.zone.record.list")));
+            let ttl = try!(member_value(record, "ttl")
+                .ok_or(unexpected_response("domain.zone.record.list")));
+
+            records.push(RecordInfo {
+                id: try!(id.parse::<u32>()),
+                name: member_value(record, "name").unwrap_or_default(),
+                type_: member_value(record, "type").unwrap_or_default(),
+                ttl: try!(ttl.parse::<u32>()),
+                value: member_value(record, "value").unwrap_or_default(),
+            });
+        }
+
+        Ok(records)
     }
 
-    pub fn domain_zone_version_new(&self, zone_id: &u32) -> u16 {
+    pub fn domain_zone_version_new(&self, zone_id: &u32) -> Result<u16> {
 
         trace!("domain_zone_version_new - zone_id: {:?}", zone_id);
 
@@ -142,28 +185,33 @@ impl<'a> GandiRPC<'a> {
         request = request.argument(zone_id);
         request = request.finalize();
 
-        let response = client.remote_call(&request).unwrap();
+        let response = try!(remote_call(&client, &request));
 
         let regex = Regex::new(r"<int>([0-9]+)</int>").unwrap();
 
-        let caps = regex.captures(&*response.body).unwrap();
+        let new_zone_version = try!(regex.captures(&*response.body)
+            .and_then(|caps| caps.at(1))
+            .ok_or(unexpected_response("domain.zone.version.new")));
 
-        caps.at(1).unwrap().parse::<u16>().ok().unwrap()
+        Ok(try!(new_zone_version.parse::<u16>()))
     }
 
     pub fn domain_zone_record_update(&self,
                                      record_name: &str,
                                      record_type: &str,
-                                     ip_addr: &IpAddr,
+                                     value: &str,
+                                     ttl: &u32,
                                      zone_id: &u32,
                                      zone_version: &u16,
-                                     new_record_id: &u32) {
+                                     new_record_id: &u32)
+                                     -> Result<()> {
 
-        trace!("domain_zone_record_update - record_name: {:?} - record_type: {:?} - ip_addr: \
-                {:?} - zone_id: {:?} - zone_version: {:?} - new_record_id: {:?}",
+        trace!("domain_zone_record_update - record_name: {:?} - record_type: {:?} - value: \
+                {:?} - ttl: {:?} - zone_id: {:?} - zone_version: {:?} - new_record_id: {:?}",
                record_name,
                record_type,
-               ip_addr,
+               value,
+               ttl,
                zone_id,
                zone_version,
                new_record_id);
@@ -183,12 +231,14 @@ impl<'a> GandiRPC<'a> {
             name: String,
             type_: String,
             value: String,
+            ttl: u32,
         }
 
         let record = Record {
             name: record_name.to_string(),
             type_: record_type.to_string(),
-            value: ip_addr.to_string(),
+            value: value.to_string(),
+            ttl: *ttl,
         };
 
         request = request.argument(&record);
@@ -198,10 +248,58 @@ impl<'a> GandiRPC<'a> {
         // Horrible hack, because 'type' is a reserved keyword ...
         request.body = request.body.replace("type_", "type");
 
-        client.remote_call(&request); // ignore response
+        let response = try!(remote_call(&client, &request));
+
+        // Updated records are returned, none means that nothing was updated
+        match response.body.contains("<struct>") {
+            true => Ok(()),
+            false => Err(unexpected_response("domain.zone.record.update")),
+        }
     }
 
-    pub fn domain_zone_version_set(&self, zone_id: &u32, zone_version: &u16) -> bool {
+    /// Returns the currently active version of the zone.
+    pub fn domain_zone_info(&self, zone_id: &u32) -> Result<u16> {
+
+        trace!("domain_zone_info - zone_id: {:?}", zone_id);
+
+        let (client, mut request) = self.get_gandi_client("domain.zone.info");
+        request = request.argument(zone_id);
+        request = request.finalize();
+
+        let response = try!(remote_call(&client, &request));
+
+        let regex = Regex::new(r"<name>version</name>\s*<value>\s*<int>([0-9]+)</int>").unwrap();
+
+        let active_version = try!(regex.captures(&*response.body)
+            .and_then(|caps| caps.at(1))
+            .ok_or(unexpected_response("domain.zone.info")));
+
+        Ok(try!(active_version.parse::<u16>()))
+    }
+
+    /// Returns all the versions of the zone, active one included.
+    pub fn domain_zone_version_list(&self, zone_id: &u32) -> Result<Vec<u16>> {
+
+        trace!("domain_zone_version_list - zone_id: {:?}", zone_id);
+
+        let (client, mut request) = self.get_gandi_client("domain.zone.version.list");
+        request = request.argument(zone_id);
+        request = request.finalize();
+
+        let response = try!(remote_call(&client, &request));
+
+        let regex = Regex::new(r"<name>id</name>\s*<value>\s*<int>([0-9]+)</int>").unwrap();
+
+        let mut versions = Vec::new();
+        for caps in regex.captures_iter(&*response.body) {
+            let version = try!(caps.at(1).unwrap().parse::<u16>());
+            versions.push(version);
+        }
+
+        Ok(versions)
+    }
+
+    pub fn domain_zone_version_set(&self, zone_id: &u32, zone_version: &u16) -> Result<bool> {
 
         trace!("domain_zone_version_set - zone_id: {:?} - zone_version: {:?}",
                zone_id,
@@ -212,35 +310,50 @@ impl<'a> GandiRPC<'a> {
         request = request.argument(zone_version);
         request = request.finalize();
 
-        let response = client.remote_call(&request).unwrap();
+        let response = try!(remote_call(&client, &request));
 
-        let regex = Regex::new(r"<boolean>([0-1]*)</boolean>").unwrap();
+        let result = try!(parse_boolean(&response, "domain.zone.version.set"));
 
-        let caps = regex.captures(&*response.body).unwrap();
+        debug!("Activate version result: {}", result);
 
-        let result = caps.at(1).unwrap();
+        Ok(result)
+    }
 
-        debug!("Activate version result: {}", result);
+    pub fn domain_zone_version_delete(&self, zone_id: &u32, zone_version: &u16) -> Result<bool> {
 
-        match result {
-            "1" => true,
-            "0" | _ => false,
-        }
+        trace!("domain_zone_version_delete - zone_id: {:?} - zone_version: {:?}",
+               zone_id,
+               zone_version);
+
+        let (client, mut request) = self.get_gandi_client("domain.zone.version.delete");
+        request = request.argument(zone_id);
+        request = request.argument(zone_version);
+        request = request.finalize();
+
+        let response = try!(remote_call(&client, &request));
+
+        let result = try!(parse_boolean(&response, "domain.zone.version.delete"));
 
+        debug!("Delete version result: {}", result);
+
+        Ok(result)
     }
 
     pub fn domain_zone_record_add(&self,
                                   record_name: &str,
                                   record_type: &str,
-                                  ip_addr: &IpAddr,
+                                  value: &str,
+                                  ttl: &u32,
                                   zone_id: &u32,
-                                  zone_version: &u16) {
+                                  zone_version: &u16)
+                                  -> Result<()> {
 
-        trace!("domain_zone_record_add - record_name: {:?} - record_type: {:?} - ip_addr: {:?} - \
-                zone_id: {:?} - zone_version: {:?}",
+        trace!("domain_zone_record_add - record_name: {:?} - record_type: {:?} - value: {:?} - \
+                ttl: {:?} - zone_id: {:?} - zone_version: {:?}",
                record_name,
                record_type,
-               ip_addr,
+               value,
+               ttl,
                zone_id,
                zone_version);
 
@@ -253,12 +366,60 @@ impl<'a> GandiRPC<'a> {
             name: String,
             type_: String,
             value: String,
+            ttl: u32,
+        }
+
+        let record = Record {
+            name: record_name.to_string(),
+            type_: record_type.to_string(),
+            value: value.to_string(),
+            ttl: *ttl,
+        };
+
+        request = request.argument(&record);
+
+        request = request.finalize();
+
+        // Horrible hack, because 'type' is a reserved keyword ...
+        request.body = request.body.replace("type_", "type");
+
+        let response = try!(remote_call(&client, &request));
+
+        // The created record is returned, with its id
+        match member_value(&response.body, "id").and_then(|id| id.parse::<u32>().ok()) {
+            Some(_) => Ok(()),
+            None => Err(unexpected_response("domain.zone.record.add")),
+        }
+    }
+
+    /// Returns the number of deleted records
+    pub fn domain_zone_record_delete(&self,
+                                     record_name: &str,
+                                     record_type: &str,
+                                     zone_id: &u32,
+                                     zone_version: &u16)
+                                     -> Result<u32> {
+
+        trace!("domain_zone_record_delete - record_name: {:?} - record_type: {:?} - zone_id: \
+                {:?} - zone_version: {:?}",
+               record_name,
+               record_type,
+               zone_id,
+               zone_version);
+
+        let (client, mut request) = self.get_gandi_client("domain.zone.record.delete");
+        request = request.argument(zone_id);
+        request = request.argument(zone_version);
+
+        #[derive(Debug,RustcEncodable,RustcDecodable)]
+        struct Record {
+            name: String,
+            type_: String,
         }
 
         let record = Record {
             name: record_name.to_string(),
             type_: record_type.to_string(),
-            value: ip_addr.to_string(),
         };
 
         request = request.argument(&record);
@@ -268,6 +429,177 @@ impl<'a> GandiRPC<'a> {
         // Horrible hack, because 'type' is a reserved keyword ...
         request.body = request.body.replace("type_", "type");
 
-        client.remote_call(&request); // ignore response
+        let response = try!(remote_call(&client, &request));
+
+        let regex = Regex::new(r"<int>([0-9]+)</int>").unwrap();
+
+        let deleted = try!(regex.captures(&*response.body)
+            .and_then(|caps| caps.at(1))
+            .ok_or(unexpected_response("domain.zone.record.delete")));
+
+        Ok(try!(deleted.parse::<u32>()))
+    }
+}
+
+/// Records of a domain.zone.record.list response, those without value or id being skipped
+fn zones(body: &str) -> Vec<Zone> {
+    let regex = Regex::new(r"(?s)<struct>(.*?)</struct>").unwrap();
+
+    regex.captures_iter(body)
+        .filter_map(|caps| {
+            let record = caps.at(1).unwrap();
+
+            let maybe_value = member_value(record, "value");
+            let maybe_record_id = member_value(record, "id")
+                .and_then(|val| val.parse::<u32>().ok());
+            let maybe_ttl = member_value(record, "ttl").and_then(|val| val.parse::<u32>().ok());
+
+            maybe_value.and_then(|value| {
+                maybe_record_id.map(|id| {
+                    Zone {
+                        value: value,
+                        record_id: id,
+                        ttl: maybe_ttl.unwrap_or(DEFAULT_TTL),
+                    }
+                })
+            })
+        })
+        .collect()
+}
+
+/// Returns the value of the first struct member named `name`.
+fn member_value(body: &str, name: &str) -> Option<String> {
+    let regex = Regex::new(&format!(r"<name>{}</name>\s*<value>\s*(?:<(?:string|int)>)?([^<]*)",
+                                    name))
+        .unwrap();
+
+    regex.captures(body)
+        .and_then(|caps| caps.at(1))
+        .map(|val| unescape(val.trim()))
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn parse_boolean(response: &XMLRPCResponse, rpc_action: &str) -> Result<bool> {
+    let regex = Regex::new(r"<boolean>([0-1]*)</boolean>").unwrap();
+
+    let result = try!(regex.captures(&*response.body)
+        .and_then(|caps| caps.at(1))
+        .ok_or(unexpected_response(rpc_action)));
+
+    match result {
+        "1" => Ok(true),
+        "0" | _ => Ok(false),
+    }
+}
+
+/// Sends `request`, a fault due to the API key is returned as an `Error::Auth`
+fn remote_call(client: &XMLRPCClient, request: &XMLRPCRequest) -> Result<XMLRPCResponse> {
+    API_CALLS.fetch_add(1, Ordering::Relaxed);
+
+    client.remote_call(request, idempotence(&request.method)).map_err(|err| {
+        match err {
+            Error::XmlRpc(ref fault) if is_auth_fault(fault) => Error::Auth(fault.clone()),
+            err => err,
+        }
+    })
+}
+
+/// Lookups can be sent again on failure, but not the methods modifying zones, e.g.
+/// `domain.zone.version.new`
+fn idempotence(method: &str) -> Idempotence {
+    match method.rsplit('.').next() {
+        Some("info") | Some("list") | Some("count") => Idempotence::Idempotent,
+        _ => Idempotence::NotIdempotent,
+    }
+}
+
+/// Number of calls to the API since the start of the process, failed ones included
+pub fn api_calls() -> u64 {
+    API_CALLS.load(Ordering::Relaxed) as u64
+}
+
+/// Gandi answers "Error on object : OBJECT_ACCOUNT (CAUSE_NORIGHT) [Invalid API key]" to an
+/// unknown API key, and CAUSE_NORIGHT to a key without access to the domain
+fn is_auth_fault(fault: &str) -> bool {
+    fault.contains("CAUSE_NORIGHT") || fault.contains("Invalid API key")
+}
+
+fn unexpected_response(rpc_action: &str) -> Error {
+    Error::XmlRpc(format!("Unexpected response to {}", rpc_action))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{idempotence, is_auth_fault, member_value, zones};
+    use http::Idempotence;
+
+    static RECORD_LIST: &'static str = "<?xml version='1.0'?>
+        <methodResponse><params><param><value><array><data><value><struct>
+        <member><name>id</name><value><int>1234</int></value></member>
+        <member><name>name</name><value><string>home</string></value></member>
+        <member><name>ttl</name><value><int>300</int></value></member>
+        <member><name>type</name><value><string>AAAA</string></value></member>
+        <member><name>value</name><value><string>2a01:ca07::3117</string></value></member>
+        </struct></value></data></array></value></param></params></methodResponse>";
+
+    fn member(name: &str) -> Option<String> {
+        member_value(RECORD_LIST, name)
+    }
+
+    #[test]
+    fn record_members() {
+        assert_eq!(Some("1234".to_string()), member("id"));
+        assert_eq!(Some("300".to_string()), member("ttl"));
+        assert_eq!(Some("AAAA".to_string()), member("type"));
+        assert_eq!(Some("2a01:ca07::3117".to_string()), member("value"));
+    }
+
+    #[test]
+    fn record_set() {
+        let body = RECORD_LIST.replace("</struct>",
+                                       "</struct></value><value><struct>\
+                                        <member><name>id</name><value><int>1235</int></value>\
+                                        </member><member><name>value</name><value><string>\
+                                        2a01:ca07::3118</string></value></member></struct>");
+        let zones = zones(&body);
+
+        assert_eq!(2, zones.len());
+        assert_eq!((1234, 300), (zones[0].record_id, zones[0].ttl));
+        assert_eq!("2a01:ca07::3118", zones[1].value);
+    }
+
+    #[test]
+    fn escaped_member() {
+        let body = "<member><name>value</name><value><string>&quot;a &amp; b&quot;</string>\
+                    </value></member>";
+        assert_eq!(Some("\"a & b\"".to_string()), member_value(body, "value"));
+    }
+
+    #[test]
+    fn missing_member() {
+        assert_eq!(None, member("zone_id"));
+    }
+
+    #[test]
+    fn auth_faults() {
+        assert!(is_auth_fault("domain.info failed: Error on object : OBJECT_ACCOUNT \
+                               (CAUSE_NORIGHT) [Invalid API key]"));
+        assert!(!is_auth_fault("domain.info failed: Error on object : OBJECT_DOMAIN \
+                                (CAUSE_NOTFOUND) [Domain 'example.com' doesn't exist.]"));
+    }
+
+    #[test]
+    fn retried_methods() {
+        assert_eq!(Idempotence::Idempotent, idempotence("domain.zone.record.list"));
+        assert_eq!(Idempotence::Idempotent, idempotence("domain.info"));
+        assert_eq!(Idempotence::NotIdempotent, idempotence("domain.zone.version.new"));
+        assert_eq!(Idempotence::NotIdempotent, idempotence("domain.zone.record.add"));
     }
 }
@@ -0,0 +1,214 @@
+use dns::Outcome;
+use error::Error;
+use error::Result;
+use http::{HttpClient, HttpSettings, Idempotence};
+use hyper::header::ContentType;
+use rustc_serialize::json::Json;
+use secret;
+use shell;
+use std::collections::BTreeMap;
+use std::process::Stdio;
+
+// Hooks run once a record has been updated or created with a new IP address, e.g. to
+// reconfigure firewall allow-lists. They can't roll back the DNS change: failures are only
+// reported.
+
+/// The change of IP address which triggers the hooks
+pub struct IpChange<'a> {
+    pub domain: &'a str,
+    pub record_name: &'a str,
+    pub outcome: &'a Outcome,
+}
+
+impl<'a> IpChange<'a> {
+    /// Returns `None` if the record hasn't changed
+    pub fn from_outcome(domain: &'a str,
+                        record_name: &'a str,
+                        outcome: &'a Outcome)
+                        -> Option<IpChange<'a>> {
+        match outcome {
+            &Outcome::Unchanged(_) => None,
+            _ => {
+                Some(IpChange {
+                    domain: domain,
+                    record_name: record_name,
+                    outcome: outcome,
+                })
+            }
+        }
+    }
+
+    fn old_ip(&self) -> String {
+        match self.outcome {
+            &Outcome::Updated { ref previous, .. } => previous.to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Environment variables given to the `--on-change` command
+    pub fn environment(&self) -> Vec<(&'static str, String)> {
+        vec![("GDU_OLD_IP", self.old_ip()),
+             ("GDU_NEW_IP", self.outcome.ip_addr().to_string()),
+             ("GDU_RECORD", self.record_name.to_string()),
+             ("GDU_DOMAIN", self.domain.to_string()),
+             ("GDU_ACTION", self.outcome.name().to_string())]
+    }
+
+    /// JSON payload POSTed to the `--webhook` URL
+    pub fn payload(&self) -> Json {
+        let mut payload = BTreeMap::new();
+        payload.insert("domain".to_string(), Json::String(self.domain.to_string()));
+        payload.insert("record".to_string(),
+                       Json::String(self.record_name.to_string()));
+        payload.insert("action".to_string(),
+                       Json::String(self.outcome.name().to_string()));
+        payload.insert("old_ip".to_string(),
+                       match self.outcome {
+                           &Outcome::Updated { .. } => Json::String(self.old_ip()),
+                           _ => Json::Null,
+                       });
+        payload.insert("new_ip".to_string(),
+                       Json::String(self.outcome.ip_addr().to_string()));
+        Json::Object(payload)
+    }
+}
+
+/// Runs `command` with the shell, fails if it doesn't exit successfully. Its output is
+/// logged, stdout being kept for the output of gdu, e.g. the JSON result document. The API key
+/// isn't given to the command.
+pub fn run_command(command: &str, change: &IpChange) -> Result<()> {
+    info!("Run on-change command: {}", command);
+
+    let mut shell = shell::command(command);
+    shell.stdin(Stdio::null()).stderr(Stdio::inherit()).env_remove(secret::APIKEY_ENV);
+
+    for (name, value) in change.environment() {
+        shell.env(name, value);
+    }
+
+    let output = try!(shell.output());
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        info!("On-change command output: {}", line);
+    }
+
+    match output.status.success() {
+        true => Ok(()),
+        false => {
+            Err(Error::Hook(format!("On-change command '{}' failed: {}", command, output.status)))
+        }
+    }
+}
+
+/// POSTs the JSON payload of the change to `url`, fails if the response isn't a success. The
+/// call isn't retried, the webhook being notified at most once.
+pub fn post_webhook(url: &str, change: &IpChange, http: &HttpSettings) -> Result<()> {
+    info!("Call webhook: {}", url);
+
+    let body = change.payload().to_string();
+    debug!("Webhook payload: {}", body);
+
+    let response = try!(try!(HttpClient::new(http))
+        .post(url, ContentType::json(), &body, Idempotence::NotIdempotent));
+
+    match response.status.is_success() {
+        true => Ok(()),
+        false => Err(Error::Hook(format!("Webhook '{}' failed: {}", url, response.status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::{Outcome, RecordType, RecordValue};
+    use http::HttpSettings;
+    use http::testing::{Behavior, StandIn};
+    use rustc_serialize::json::Json;
+    use secret;
+    use std::env;
+
+    fn updated() -> Outcome {
+        Outcome::Updated {
+            previous: RecordValue::parse(&RecordType::A, "100.3.5.4").unwrap(),
+            ip_addr: "100.3.5.5".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn unchanged_record() {
+        let outcome = Outcome::Unchanged("100.3.5.5".parse().unwrap());
+        assert!(IpChange::from_outcome("example.com", "home", &outcome).is_none());
+    }
+
+    #[test]
+    fn environment() {
+        let outcome = updated();
+        let change = IpChange::from_outcome("example.com", "home", &outcome).unwrap();
+
+        assert_eq!(vec![("GDU_OLD_IP", "100.3.5.4".to_string()),
+                        ("GDU_NEW_IP", "100.3.5.5".to_string()),
+                        ("GDU_RECORD", "home".to_string()),
+                        ("GDU_DOMAIN", "example.com".to_string()),
+                        ("GDU_ACTION", "updated".to_string())],
+                   change.environment());
+    }
+
+    #[test]
+    fn created_payload() {
+        let outcome = Outcome::Created("2a01:ca07::3117".parse().unwrap());
+        let change = IpChange::from_outcome("example.com", "home", &outcome).unwrap();
+        let payload = change.payload();
+
+        assert_eq!(Some(&Json::String("created".to_string())), payload.find("action"));
+        assert_eq!(Some(&Json::Null), payload.find("old_ip"));
+        assert_eq!(Some(&Json::String("2a01:ca07::3117".to_string())),
+                   payload.find("new_ip"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_with_environment() {
+        let outcome = updated();
+        let change = IpChange::from_outcome("example.com", "home", &outcome).unwrap();
+
+        assert!(run_command("test \"$GDU_NEW_IP\" = 100.3.5.5 -a \"$GDU_ACTION\" = updated",
+                            &change)
+            .is_ok());
+        assert!(run_command("exit 3", &change).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_without_apikey() {
+        let outcome = updated();
+        let change = IpChange::from_outcome("example.com", "home", &outcome).unwrap();
+        env::set_var(secret::APIKEY_ENV, "my-api-key");
+
+        let result = run_command("test -z \"${GDU_APIKEY+set}\"", &change);
+        env::remove_var(secret::APIKEY_ENV);
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn webhook() {
+        let server = StandIn::start(vec![Behavior::Respond("204 No Content", "")]);
+        let outcome = updated();
+        let change = IpChange::from_outcome("example.com", "home", &outcome).unwrap();
+
+        post_webhook(&server.url, &change, &HttpSettings::default()).unwrap();
+
+        let request = server.requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with("POST / HTTP/1.1"));
+        assert!(request.contains("\"new_ip\":\"100.3.5.5\""));
+    }
+
+    #[test]
+    fn failed_webhook() {
+        let server = StandIn::start(vec![Behavior::Respond("500 Internal Server Error", "")]);
+        let outcome = updated();
+        let change = IpChange::from_outcome("example.com", "home", &outcome).unwrap();
+
+        assert!(post_webhook(&server.url, &change, &HttpSettings::default()).is_err());
+        assert_eq!(1, server.request_count());
+    }
+}
@@ -0,0 +1,417 @@
+use log;
+use log::{LogLevel, LogLevelFilter, LogMetadata, LogRecord, SetLoggerError};
+use rustc_serialize::json::Json;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+use std::sync::Mutex;
+use systemd;
+use time;
+
+// Logging: text, logfmt or JSON lines on stderr or in a rotated file, or entries of the systemd
+// journal, filtered per module as with RUST_LOG. Log entries carry the structured fields of the
+// run, e.g. the record being updated.
+
+/// Target of the logs of record changes, the only ones kept with `--quiet` besides errors and
+/// warnings
+pub const CHANGES: &'static str = "gdu::changes";
+
+/// Rotated log files kept, e.g. gdu.log.1 to gdu.log.5
+const ROTATED_FILES: u32 = 5;
+
+thread_local! {
+    /// Structured fields added to each log entry, e.g. the record being updated
+    static FIELDS: RefCell<Vec<(&'static str, String)>> = RefCell::new(Vec::new())
+}
+
+/// Sets a field of the next log entries, e.g. ("IP", "100.3.5.5")
+pub fn set_field(name: &'static str, value: &str) {
+    FIELDS.with(|fields| {
+        let mut fields = fields.borrow_mut();
+        fields.retain(|&(field, _)| field != name);
+        fields.push((name, value.to_string()));
+    });
+}
+
+/// Removes the structured fields, e.g. before another run
+pub fn clear_fields() {
+    FIELDS.with(|fields| fields.borrow_mut().clear());
+}
+
+/// Structured fields of the current thread, with upper case names
+pub fn fields() -> Vec<(&'static str, String)> {
+    FIELDS.with(|fields| fields.borrow().clone())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// "2016-10-18 12:00:00,123 - INFO - message"
+    Text,
+    /// "time=2016-10-18T10:00:00.123Z level=info module=gdu msg=message"
+    Logfmt,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<LogFormat, String> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "logfmt" => Ok(LogFormat::Logfmt),
+            "json" => Ok(LogFormat::Json),
+            value => Err(format!("Unknown value for log format: {}", value)),
+        }
+    }
+}
+
+/// Log levels per module, as with RUST_LOG, e.g. "info,xmlrpc=trace"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    default: LogLevelFilter,
+    /// Module paths, without the crate name, and their levels
+    modules: Vec<(String, LogLevelFilter)>,
+}
+
+impl Filter {
+    pub fn new(default: LogLevelFilter) -> Filter {
+        Filter {
+            default: default,
+            modules: Vec::new(),
+        }
+    }
+
+    /// Adds the directives of `spec`: a level alone replaces the default one
+    pub fn parse(mut self, spec: &str) -> StdResult<Filter, String> {
+        for directive in spec.split(',').map(|directive| directive.trim()) {
+            if directive.is_empty() {
+                continue;
+            }
+
+            let mut parts = directive.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(level), None) => self.default = try!(level_filter(level)),
+                (Some(module), Some(level)) => {
+                    let module = module.trim_left_matches("gdu::").to_string();
+                    self.modules.push((module, try!(level_filter(level))));
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Level of the most specific directive matching the `target` module, e.g.
+    /// "gdu::xmlrpc::client"
+    pub fn level(&self, target: &str) -> LogLevelFilter {
+        let target = target.trim_left_matches("gdu::");
+
+        self.modules
+            .iter()
+            .filter(|&&(ref module, _)| {
+                target == module || target.starts_with(&format!("{}::", module))
+            })
+            .max_by_key(|&&(ref module, _)| module.len())
+            .map(|&(_, level)| level)
+            .unwrap_or(self.default)
+    }
+
+    /// Most verbose level of all the modules
+    fn max_level(&self) -> LogLevelFilter {
+        self.modules.iter().map(|&(_, level)| level).fold(self.default, ::std::cmp::max)
+    }
+}
+
+fn level_filter(level: &str) -> StdResult<LogLevelFilter, String> {
+    level.parse::<LogLevelFilter>().map_err(|_| format!("Unknown log level: {}", level))
+}
+
+/// Where logs are written
+pub enum Destination {
+    Stderr,
+    /// File rotated once larger than the size, in bytes
+    File(String, u64),
+    /// systemd journal, whatever the format
+    Journal,
+}
+
+enum Output {
+    Stderr,
+    File(RotatingFile),
+    Journal,
+}
+
+struct Logger {
+    filter: Filter,
+    format: LogFormat,
+    output: Mutex<Output>,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut output = self.output.lock().unwrap();
+        let result = match *output {
+            Output::Stderr => write!(io::stderr(), "{}\n", render(self.format, record)),
+            Output::File(ref mut file) => {
+                file.write_line(&format!("{}\n", render(self.format, record)))
+            }
+            Output::Journal => systemd::log_to_journal(record),
+        };
+
+        if let Err(err) = result {
+            let _ = writeln!(io::stderr(),
+                             "Failed to log: {} - {}",
+                             err,
+                             render(LogFormat::Text, record));
+        }
+    }
+}
+
+/// Installs the logger
+pub fn init(filter: Filter,
+            format: LogFormat,
+            destination: Destination)
+            -> StdResult<(), String> {
+    let output = match destination {
+        Destination::Stderr => Output::Stderr,
+        Destination::File(path, max_size) => {
+            Output::File(try!(RotatingFile::open(&path, max_size)
+                .map_err(|err| format!("Failed to open log file '{}': {}", path, err))))
+        }
+        Destination::Journal => Output::Journal,
+    };
+
+    log::set_logger(|max_level| {
+            max_level.set(filter.max_level());
+            Box::new(Logger {
+                filter: filter,
+                format: format,
+                output: Mutex::new(output),
+            })
+        })
+        .map_err(|err: SetLoggerError| err.to_string())
+}
+
+fn render(format: LogFormat, record: &LogRecord) -> String {
+    let fields = fields();
+    let message = record.args().to_string();
+
+    match format {
+        LogFormat::Text => {
+            let t = time::now();
+            format!("{},{:03} - {} - {}",
+                    time::strftime("%Y-%m-%d %H:%M:%S", &t).unwrap(),
+                    t.tm_nsec / 1000_000,
+                    record.level(),
+                    message)
+        }
+        LogFormat::Logfmt => {
+            let mut pairs = vec![("time".to_string(), timestamp()),
+                                 ("level".to_string(), level_name(record.level())),
+                                 ("module".to_string(), record.target().to_string()),
+                                 ("msg".to_string(), message)];
+            for (name, value) in fields {
+                pairs.push((name.to_lowercase(), value));
+            }
+
+            pairs.iter()
+                .map(|&(ref name, ref value)| format!("{}={}", name, logfmt_value(value)))
+                .collect::<Vec<String>>()
+                .join(" ")
+        }
+        LogFormat::Json => {
+            let mut object = BTreeMap::new();
+            object.insert("time".to_string(), Json::String(timestamp()));
+            object.insert("level".to_string(), Json::String(level_name(record.level())));
+            object.insert("module".to_string(),
+                          Json::String(record.target().to_string()));
+            object.insert("message".to_string(), Json::String(message));
+            for (name, value) in fields {
+                object.insert(name.to_lowercase(), Json::String(value));
+            }
+            Json::Object(object).to_string()
+        }
+    }
+}
+
+/// UTC time, e.g. "2016-10-18T10:00:00.123Z"
+fn timestamp() -> String {
+    let t = time::now_utc();
+    format!("{}.{:03}Z",
+            time::strftime("%Y-%m-%dT%H:%M:%S", &t).unwrap(),
+            t.tm_nsec / 1000_000)
+}
+
+fn level_name(level: LogLevel) -> String {
+    level.to_string().to_lowercase()
+}
+
+/// Value quoted if it holds spaces, quotes or equal signs
+fn logfmt_value(value: &str) -> String {
+    let plain = |c: char| c != ' ' && c != '"' && c != '=' && c >= ' ';
+    if !value.is_empty() && value.chars().all(plain) {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Log file renamed with a ".1" suffix once larger than `max_size`, older ones being shifted
+/// up to ".5"
+struct RotatingFile {
+    path: String,
+    max_size: u64,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &str, max_size: u64) -> io::Result<RotatingFile> {
+        let file = try!(OpenOptions::new().create(true).append(true).open(path));
+        let size = try!(file.metadata()).len();
+
+        Ok(RotatingFile {
+            path: path.to_string(),
+            max_size: max_size,
+            file: file,
+            size: size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            try!(self.rotate());
+        }
+
+        try!(self.file.write_all(line.as_bytes()));
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..ROTATED_FILES).rev() {
+            // Missing when fewer files have been rotated yet
+            let _ = fs::rename(format!("{}.{}", self.path, index),
+                               format!("{}.{}", self.path, index + 1));
+        }
+        try!(fs::rename(&self.path, format!("{}.1", self.path)));
+
+        self.file = try!(OpenOptions::new().create(true).append(true).open(&self.path));
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::{RotatingFile, logfmt_value};
+    use log::LogLevelFilter;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+
+    #[test]
+    fn filter() {
+        let filter = Filter::new(LogLevelFilter::Info)
+            .parse("xmlrpc=trace,gdu::http=warn")
+            .unwrap();
+
+        assert_eq!(LogLevelFilter::Info, filter.level("gdu"));
+        assert_eq!(LogLevelFilter::Info, filter.level("gdu::xmlrpcx"));
+        assert_eq!(LogLevelFilter::Trace, filter.level("gdu::xmlrpc::client"));
+        assert_eq!(LogLevelFilter::Warn, filter.level("gdu::http"));
+        assert_eq!(LogLevelFilter::Trace, filter.max_level());
+
+        let filter = Filter::new(LogLevelFilter::Info)
+            .parse("debug, xmlrpc::client=off")
+            .unwrap();
+        assert_eq!(LogLevelFilter::Debug, filter.level("gdu::dns"));
+        assert_eq!(LogLevelFilter::Off, filter.level("gdu::xmlrpc::client"));
+
+        assert!(Filter::new(LogLevelFilter::Info).parse("xmlrpc=loud").is_err());
+    }
+
+    #[test]
+    fn quiet_filter() {
+        let filter = Filter::new(LogLevelFilter::Warn)
+            .parse(&format!("{}=info", CHANGES))
+            .unwrap();
+
+        assert_eq!(LogLevelFilter::Warn, filter.level("gdu::dns"));
+        assert_eq!(LogLevelFilter::Info, filter.level(CHANGES));
+    }
+
+    #[test]
+    fn logfmt_values() {
+        assert_eq!("home", logfmt_value("home"));
+        assert_eq!("\"\"", logfmt_value(""));
+        assert_eq!("\"Update record 'home'\"", logfmt_value("Update record 'home'"));
+        assert_eq!("\"a=\\\"b\\\"\"", logfmt_value("a=\"b\""));
+    }
+
+    #[test]
+    fn fields() {
+        set_field("RECORD", "home");
+        set_field("IP", "100.3.5.4");
+        set_field("IP", "100.3.5.5");
+
+        assert_eq!(vec![("RECORD", "home".to_string()), ("IP", "100.3.5.5".to_string())],
+                   super::fields());
+
+        clear_fields();
+        assert!(super::fields().is_empty());
+    }
+
+    #[test]
+    fn rotation() {
+        let path = env::temp_dir().join("gdu-test-rotation.log");
+        let path = path.to_str().unwrap();
+        for suffix in &["", ".1", ".2"] {
+            let _ = fs::remove_file(format!("{}{}", path, suffix));
+        }
+
+        let mut file = RotatingFile::open(path, 10).unwrap();
+        file.write_line("first\n").unwrap();
+        file.write_line("second\n").unwrap();
+        file.write_line("third\n").unwrap();
+
+        let read = |path: String| {
+            let mut content = String::new();
+            fs::File::open(path).unwrap().read_to_string(&mut content).unwrap();
+            content
+        };
+        assert_eq!("third\n", read(path.to_string()));
+        assert_eq!("second\n", read(format!("{}.1", path)));
+        assert_eq!("first\n", read(format!("{}.2", path)));
+
+        for suffix in &["", ".1", ".2"] {
+            fs::remove_file(format!("{}{}", path, suffix)).unwrap();
+        }
+    }
+}
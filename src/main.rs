@@ -6,142 +6,1031 @@ mod myip;
 // DNS providers
 mod dns;
 
+// Registries of the DNS and IP address providers compiled in
+mod registry;
+
 // Configuration
 mod config;
 
 mod gandi;
 
+// API keys, kept out of logs
+mod secret;
+
+// Config file with credential profiles
+mod profiles;
+
+// Zone export
+mod export;
+
+// Zonefile synchronization
+mod zonefile;
+mod sync;
+
+// IPv6 prefix delegation
+mod prefix;
+
 mod xmlrpc;
 
+// Shared HTTP layer
+mod http;
+mod socket;
+
+// Result document of a run
+mod report;
+
+// ACME DNS-01 challenges
+mod acme;
+mod resolver;
+mod propagation;
+
+// Hooks run on IP address changes
+mod hooks;
+
+// Shell commands of hooks and API keys
+mod shell;
+
+// Prometheus metrics in daemon mode
+mod metrics;
+
+// systemd notifications and journal logging
+mod systemd;
+
+// Logging: formats, filters and destinations
+mod logging;
+
 #[macro_use]
 extern crate clap;
 
 #[macro_use]
 extern crate log;
-extern crate env_logger;
 
 extern crate rustc_serialize;
 extern crate regex;
 extern crate hyper;
+extern crate openssl;
+extern crate libc;
 
 extern crate time;
 
 extern crate xml;
 
-use clap::{Arg, App};
-use config::Config;
+extern crate toml;
+
+use acme::AcmeAction;
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+use config::{AcmeOptions, Command, Config, DaemonOptions, DeleteOptions, ManagedRecords,
+             PrefixOptions, PrefixSource, PropagationOptions, SetOptions, StatusOptions,
+             UpdateOptions};
+use dns::ChangeSet;
+use dns::DNSProvider;
 use dns::DNSProviderFactory;
+use dns::DeclaredRecord;
+use dns::Outcome;
+use dns::ProviderType;
 use dns::Record;
-use env_logger::LogBuilder;
+use dns::RecordType;
+use dns::RecordValue;
+use dns::ZoneRecord;
+use error::Error;
 use error::Result;
-use log::{LogRecord, LogLevelFilter};
+use export::ExportFormat;
+use http::{HttpSettings, IpFamily, Proxy};
+use log::LogLevelFilter;
+use logging::{Destination, Filter, LogFormat};
+use metrics::Metrics;
 use myip::GetMyIpAddr;
-use myip::IpProvider;
+use myip::{IpDetector, IpProvider};
+use prefix::HostSuffix;
+use profiles::{ConfigFile, ManagedRecord};
+use registry::Registry;
+use report::{OutputFormat, RecordReport, Report};
+use resolver::{Answer, QueryType};
+use socket::Source;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Exit codes, see README
+const EXIT_OK: i32 = 0;
+const EXIT_OUTDATED: i32 = 1;
+const EXIT_NOT_FOUND: i32 = 2;
+const EXIT_NOT_PROPAGATED: i32 = 3;
+const EXIT_UPDATED: i32 = 10;
+const EXIT_CREATED: i32 = 11;
+const EXIT_IP_DETECTION_FAILED: i32 = 20;
+const EXIT_AUTH_FAILED: i32 = 21;
+const EXIT_NETWORK_FAILED: i32 = 22;
+const EXIT_FAILURE: i32 = 255;
 
 fn main() {
-    let config = build_config();
+    let (command, mut report) = build_config();
+
+    let result = match command {
+        Command::Detect(ref ip_detector) => detect(ip_detector, &mut report),
+        Command::Providers => providers(&report),
+        Command::Status(ref config, ref options) => status(config, options, &mut report),
+        Command::List(ref config) => list(config, &mut report),
+        Command::Export(ref config, ref format) => export(config, format, &mut report),
+        Command::Sync(ref config, ref zonefile) => sync(config, zonefile, &mut report),
+        Command::Update(ref config, ref options) => {
+            match options.daemon {
+                Some(ref daemon_options) => {
+                    daemon(daemon_options,
+                           &report,
+                           |run_report| update(config, options, run_report))
+                }
+                None => update(config, options, &mut report),
+            }
+        }
+        Command::UpdateAll(ref records, ref options) => {
+            match options.daemon {
+                Some(ref daemon_options) => {
+                    daemon(daemon_options,
+                           &report,
+                           |run_report| update_all(records, options, run_report))
+                }
+                None => update_all(records, options, &mut report),
+            }
+        }
+        Command::Prefix(ref config, ref options) => prefix(config, options, &mut report),
+        Command::Set(ref config, ref options) => set(config, options, &mut report),
+        Command::Delete(ref config, ref options) => delete(config, options, &mut report),
+        Command::Acme(ref config, ref options) => acme(config, options, &mut report),
+    };
 
-    match main_with_errors(&config) {
-        Ok(_) => info!("Process ends with success"),
+    match result {
+        Ok(exit_code) => {
+            info!("Process ends with success");
+            report.finish(exit_code, None);
+            process::exit(exit_code);
+        }
         Err(err) => {
             error!("Process failed with result: {}", err);
-            process::exit(-1);
+            let exit_code = failure_exit_code(&err);
+            report.finish(exit_code, Some((failure_kind(&err), err.to_string())));
+            process::exit(exit_code);
         }
     }
 }
 
-fn build_config() -> Config {
+fn provider_args<'a>(setting_args: &'a [SettingArg]) -> Vec<Arg<'a, 'a>> {
+    let mut args = credential_args(setting_args);
+    args.push(domain_arg());
+    args
+}
+
+fn credential_args<'a>(setting_args: &'a [SettingArg]) -> Vec<Arg<'a, 'a>> {
+    let mut args = vec![Arg::from_usage("--provider=[provider] 'DNS provider to use, listed by \
+                                         \"gdu providers\" (gandi if not set)'")
+                            .conflicts_with("profile"),
+                        Arg::from_usage("-c --config=[config] 'Config file with credential \
+                                         profiles and managed records'"),
+                        Arg::from_usage("--profile=[profile] 'Credential profile of the config \
+                                         file to use, instead of the settings of a DNS provider'")
+                            .requires("config")];
+
+    for setting_arg in setting_args {
+        let mut arg = Arg::with_name(setting_arg.setting)
+            .long(&setting_arg.long)
+            .value_name(setting_arg.setting)
+            .help(&setting_arg.help)
+            .takes_value(true)
+            .conflicts_with("profile");
+        if let Some(&(_, short)) = SETTING_SHORTS.iter()
+            .find(|&&(setting, _)| setting == setting_arg.setting) {
+            arg = arg.short(short);
+        }
+        args.push(arg);
+    }
+
+    args
+}
+
+/// Short options of settings, kept from before the settings came from the DNS providers, e.g.
+/// `gdu -a KEY`
+const SETTING_SHORTS: &'static [(&'static str, &'static str)] = &[("apikey", "a")];
+
+/// Option of a setting of the DNS providers, e.g. --apikey-file for apikey_file
+struct SettingArg {
+    setting: &'static str,
+    long: String,
+    help: String,
+}
+
+/// Options of the settings of all the DNS providers, with the providers taking each of them
+fn setting_args() -> Vec<SettingArg> {
+    let mut settings: Vec<(&'static str, Vec<String>)> = Vec::new();
+
+    for provider in dns::DNS_PROVIDERS.providers {
+        for &(setting, description) in provider.settings {
+            let help = match provider.defaults.iter().find(|&&(name, _)| name == setting) {
+                Some(&(_, value)) => {
+                    format!("{}: {}, {} if no setting is given",
+                            provider.name,
+                            description,
+                            value)
+                }
+                None => format!("{}: {}", provider.name, description),
+            };
+
+            match settings.iter().position(|&(name, _)| name == setting) {
+                Some(index) => settings[index].1.push(help),
+                None => settings.push((setting, vec![help])),
+            }
+        }
+    }
+
+    settings.into_iter()
+        .map(|(setting, helps)| {
+            SettingArg {
+                setting: setting,
+                long: setting.replace('_', "-"),
+                help: format!("Setting {} of the DNS provider ({})", setting, helps.join("; ")),
+            }
+        })
+        .collect()
+}
+
+fn domain_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::from_usage("-d --domain=<domain> 'The domain name whose active zonefile will be \
+                     updated, e.g. \"domain.com\"'")
+}
+
+fn write_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![Arg::from_usage("-n --dry-run 'Dry run, don't really update Gandi zone file'"),
+         Arg::from_usage("--keep-versions=[keep_versions] 'Number of inactive zone versions to \
+                          keep, older ones are deleted after each update'")]
+}
+
+fn propagation_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![Arg::from_usage("--resolver=[resolver] 'IP address of the resolver used to find the \
+                          authoritative nameservers (first nameserver of /etc/resolv.conf if not \
+                          set)'"),
+         Arg::from_usage("--propagation-timeout=[propagation_timeout] 'Maximum time to wait for \
+                          the authoritative nameservers to serve the record, in seconds'")
+             .default_value("300")]
+}
+
+/// Help of the IP provider option, listing the IP address providers available
+fn ip_provider_help() -> String {
+    // Aligned with the help of the other options
+    let indent = " ".repeat(40);
+    format!("IP address provider to use to get your own IP address.\n{}Available values for \
+             <ip-provider>:\n{}{}",
+            &indent[1..],
+            indent,
+            myip::IP_PROVIDERS.help(&indent))
+}
+
+fn ip_provider_arg<'a, 'b>(help: &'b str) -> Arg<'a, 'b> {
+    Arg::with_name("ip_provider")
+        .help(help)
+        .short("i")
+        .long("ip-provider")
+        .takes_value(true)
+        .multiple(false)
+        .required(true)
+}
+
+/// Options of the IP address detection
+fn ip_detection_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![Arg::from_usage("--ip-family=[family] 'Family of the IP address to detect, 4 or 6: the IP \
+                          address provider is only reached with this family, and an address of \
+                          the other family is rejected'"),
+         Arg::from_usage("--source-address=[address] 'Local address the IP address provider is \
+                          reached from, e.g. of one WAN link (Linux only)'")
+             .conflicts_with("interface"),
+         Arg::from_usage("--interface=[interface] 'Network interface the IP address provider is \
+                          reached from, e.g. ppp0 for one WAN link (Linux only, may require the \
+                          CAP_NET_RAW capability)'")]
+}
+
+/// Global options taking a value, skipped to find the subcommand of the command line
+const GLOBAL_OPTIONS: &'static [&'static str] = &["-o",
+                                                  "--output",
+                                                  "--log-format",
+                                                  "--log-filter",
+                                                  "--log-file",
+                                                  "--log-max-size",
+                                                  "--connect-timeout",
+                                                  "--timeout",
+                                                  "--retries",
+                                                  "--retry-backoff",
+                                                  "--proxy",
+                                                  "--no-proxy",
+                                                  "--ca-file"];
+
+/// Global flags, skipped to find the subcommand of the command line
+const GLOBAL_FLAGS: &'static [&'static str] = &["-q", "--quiet", "--journal"];
+
+/// Command line with the `update` subcommand inserted if it has none, but options, as before
+/// subcommands were introduced, e.g. `gdu -a KEY -d example.com -r home -i ipify`. Returns
+/// whether the subcommand was inserted.
+fn with_default_subcommand(mut args: Vec<OsString>) -> (Vec<OsString>, bool) {
+    let mut index = 1;
+    while let Some(arg) = args.get(index).and_then(|arg| arg.to_str()).map(|arg| arg.to_string()) {
+        let option = arg.splitn(2, '=').next().unwrap();
+        if GLOBAL_OPTIONS.contains(&&arg[..]) {
+            index += 2;
+        } else if GLOBAL_FLAGS.contains(&&arg[..]) || GLOBAL_OPTIONS.contains(&option) ||
+                  (arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| c == 'v')) {
+            index += 1;
+        } else {
+            break;
+        }
+    }
+
+    let flat = match args.get(index).and_then(|arg| arg.to_str()) {
+        Some("-h") | Some("--help") | Some("-V") | Some("--version") => false,
+        Some(arg) => arg.starts_with('-'),
+        None => false,
+    };
+    if flat {
+        args.insert(index, OsString::from("update"));
+    }
+
+    (args, flat)
+}
+
+fn build_config() -> (Command, Report) {
+    let ip_provider_help = ip_provider_help();
+    let setting_args = setting_args();
+    let (args, flat) = with_default_subcommand(env::args_os().collect());
+
     let matches = App::new("gdu")
         .version(&crate_version!()[..])
         .author("Damien Lecan <dev@dlecan.com>")
         .about("Generic DNS update, useful to update your dynamic IP address into your DNS provider zone file, e.g. Gandi or Go Daddy.\n\
             GDU detects if your ip address is IPv4 or v6 and and will create a record with type 'A' or 'AAAA' accordingly.\n\n\
             IP address can be read from several HTTP providers or from stdin.\n\
-            Only Gandi DNS provider is implemented in this version.")
-        .args_from_usage(
-            "-a --apikey=<apikey> 'Your API key provided by Gandi'
-            -d --domain=<domain> 'The domain name whose active zonefile will be updated, e.g. \"domain.com\"'
-            -n --dry-run 'Dry run, don't really update Gandi zone file'
-            -f --force 'Force new zonefile creation even if IP address isn\'t modified'
-            -r --record-name=<record_name> 'Name of the A or AAAA record to update or create (without domain)'
-            [verbose]... -v 'Verbose mode'")
-        .arg(Arg::with_name("ip_provider")
-            .help("IP address provider to use to get your own IP address.\n                                       \
-                Available values for <ip-provider>:\n                                        \
-                 ipify         : Ipify (IPv4)\n
-                 opendns       : OpenDNS (IPv6)\n                                        \
-                 -             : read IP address from stdin\n                                        \
-                 sfrlaboxfibre : French 'SFR Labox Fibre' subscribers (IPv4)")
-            .short("i")
-            .long("ip-provider")
-            .takes_value(true)
-            .multiple(false)
-            .required(true))
-        .get_matches();
+            'gdu providers' lists the DNS and IP address providers available.")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::from_usage("[verbose]... -v 'Verbose mode: debug logs, or trace logs if given \
+                              twice'")
+            .global(true))
+        .arg(Arg::from_usage("-q --quiet 'Only log record changes, warnings and errors, e.g. \
+                              from cron'")
+            .conflicts_with("verbose")
+            .global(true))
+        .arg(Arg::from_usage("--log-format=[format] 'Log format: text, logfmt or json, text if \
+                              not set'")
+            .global(true))
+        .arg(Arg::from_usage("--log-filter=[filter] 'Log levels per module, e.g. \
+                              info,xmlrpc=trace, read from the RUST_LOG environment variable if \
+                              not set'")
+            .global(true))
+        .arg(Arg::from_usage("--log-file=[file] 'File the logs are appended to instead of \
+                              stderr, rotated when too large'")
+            .conflicts_with("journal")
+            .global(true))
+        .arg(Arg::from_usage("--log-max-size=[megabytes] 'Size of the log file before rotation, \
+                              10 megabytes if not set. 5 rotated files are kept'")
+            .requires("log-file")
+            .global(true))
+        .arg(Arg::from_usage("-o --output=[output] 'Output format: text, or json for a result \
+                              document on stdout, logs being written on stderr'")
+            .global(true))
+        .arg(Arg::from_usage("--connect-timeout=[seconds] 'Maximum time to connect to HTTP \
+                              servers, 10 seconds if not set'")
+            .global(true))
+        .arg(Arg::from_usage("--timeout=[seconds] 'Maximum time to wait for each read or write \
+                              of HTTP calls, 30 seconds if not set'")
+            .global(true))
+        .arg(Arg::from_usage("--retries=[retries] 'Number of retries of failed HTTP lookups, \
+                              with exponential backoff, 2 if not set. Zone updates are never \
+                              retried'")
+            .global(true))
+        .arg(Arg::from_usage("--retry-backoff=[milliseconds] 'Delay before the first retry, \
+                              doubled before each next one, 1000 milliseconds if not set'")
+            .global(true))
+        .arg(Arg::from_usage("--journal 'Log to the systemd journal, with RECORD, IP and PROVIDER \
+                              fields, instead of stderr'")
+            .global(true))
+        .arg(Arg::from_usage("--proxy=[url] 'HTTP or SOCKS5 proxy of all HTTP calls, e.g. \
+                              http://proxy:3128 or socks5://proxy:1080, read from the HTTPS_PROXY \
+                              or HTTP_PROXY (depending on the URL), or ALL_PROXY environment \
+                              variables if not set'")
+            .global(true))
+        .arg(Arg::from_usage("--no-proxy=[hosts] 'Comma-separated hosts and domains reached \
+                              without the proxy, read from the NO_PROXY environment variable if \
+                              not set'")
+            .global(true))
+        .arg(Arg::from_usage("--ca-file=[file] 'PEM file of CA certificates trusted in addition to \
+                              the system ones, e.g. of a TLS inspecting proxy'")
+            .global(true))
+        .subcommand(SubCommand::with_name("update")
+            .about("Create or update a record with your current IP address, or all the records of \
+                    the config file")
+            .args(&credential_args(&setting_args))
+            .arg(domain_arg().required_unless("config"))
+            .args(&write_args())
+            .arg(Arg::from_usage("-r --record-name=<record_name> 'Name of the A or AAAA record \
+                                  to update or create (without domain), all the records of the \
+                                  config file if not set'")
+                .required_unless("config"))
+            .args_from_usage(
+                "-f --force 'Force new zonefile creation even if IP address isn\'t modified'
+                -t --ttl=[ttl] 'Time to live of the record, in seconds (DNS provider default if not set)'
+                --verify 'Wait until all authoritative nameservers of the domain serve the record, exits with 3 if they don\'t'
+                --detailed-exitcodes 'Exit with 10 if the record is updated and 11 if it is created, instead of 0'
+                --json 'Same as --output json'
+                --on-change=[command] 'Shell command run once the record is updated or created, with GDU_OLD_IP, GDU_NEW_IP, GDU_RECORD, GDU_DOMAIN and GDU_ACTION in its environment'
+                --webhook=[url] 'URL to POST a JSON payload to once the record is updated or created'
+                --interval=[interval] 'Run continuously, updating the record every <interval> seconds'")
+            .arg(Arg::from_usage("--metrics-listen=[address] 'Address of the HTTP endpoint \
+                                  exposing Prometheus metrics on /metrics in daemon mode, e.g. \
+                                  127.0.0.1:9253'")
+                .requires("interval"))
+            .args(&propagation_args())
+            .arg(ip_provider_arg(&ip_provider_help))
+            .args(&ip_detection_args()))
+        .subcommand(SubCommand::with_name("status")
+            .about("Compare a record with your current IP address, exits with 0 if up to date, \
+                    1 if outdated and 2 if not declared")
+            .args(&provider_args(&setting_args))
+            .arg(Arg::from_usage("-r --record-name=<record_name> 'Name of the A or AAAA record \
+                                  to check (without domain)'"))
+            .arg(ip_provider_arg(&ip_provider_help))
+            .args(&ip_detection_args()))
+        .subcommand(SubCommand::with_name("list")
+            .about("List all records of the active zonefile")
+            .args(&provider_args(&setting_args)))
+        .subcommand(SubCommand::with_name("export")
+            .about("Export all records of the active zonefile to stdout")
+            .args(&provider_args(&setting_args))
+            .arg(Arg::from_usage("--format=[format] 'Export format: bind (RFC 1035 master file), \
+                                  json or csv'")
+                .default_value("bind")))
+        .subcommand(SubCommand::with_name("sync")
+            .about("Create, update and delete A, AAAA, CNAME, MX, SRV and TXT records so that the \
+                    active zonefile matches a BIND zonefile")
+            .args(&provider_args(&setting_args))
+            .args(&write_args())
+            .arg(Arg::from_usage("<zonefile> 'RFC 1035 master file of the zone'")))
+        .subcommand(SubCommand::with_name("prefix")
+            .about("Create or update the AAAA records of LAN hosts, from the IPv6 prefix \
+                    delegated to you and the suffix of each host")
+            .args(&provider_args(&setting_args))
+            .args(&write_args())
+            .args_from_usage(
+                "--prefix-length=<prefix_length> 'Length of the delegated IPv6 prefix, e.g. 56'
+                --host=<host>... 'LAN host and the suffix of its address, e.g. \"nas=::211:32ff:fe12:3456\", can be repeated'
+                --prefix=[prefix] 'IPv6 address within the delegated prefix, e.g. of a LAN interface, instead of your current IP address'
+                -t --ttl=[ttl] 'Time to live of the records, in seconds (current one or DNS provider default if not set)'")
+            .arg(ip_provider_arg(&ip_provider_help)
+                .required_unless("prefix")
+                .conflicts_with("prefix"))
+            .args(&ip_detection_args()))
+        .subcommand(SubCommand::with_name("set")
+            .about("Create or update a record with a given value, of any supported type")
+            .args(&provider_args(&setting_args))
+            .args(&write_args())
+            .args_from_usage(
+                "-r --record-name=<record_name> 'Name of the record to update or create (without domain)'
+                --type=<record_type> 'Type of the record: A, AAAA, CNAME, MX, SRV or TXT'
+                --value=<value> 'Value of the record, as in a zonefile, e.g. \"10 mail.domain.com.\" for a MX record'
+                -t --ttl=[ttl] 'Time to live of the record, in seconds (DNS provider default if not set)'"))
+        .subcommand(SubCommand::with_name("delete")
+            .about("Delete a record from the active zonefile, exits with 2 if not declared")
+            .args(&provider_args(&setting_args))
+            .args(&write_args())
+            .args_from_usage(
+                "-r --record-name=<record_name> 'Name of the record to delete (without domain)'
+                --type=<record_type> 'Type of the record to delete: A, AAAA, CNAME, MX, SRV or TXT'"))
+        .subcommand(SubCommand::with_name("acme")
+            .about("ACME DNS-01 challenge hook: create or delete the _acme-challenge TXT record \
+                    for a domain name, 'present' waits until all authoritative nameservers \
+                    serve it, exits with 3 if they don't")
+            .args(&provider_args(&setting_args))
+            .args(&write_args())
+            .arg(Arg::from_usage("<action> 'present or cleanup'")
+                .possible_values(&["present", "cleanup"]))
+            .args_from_usage(
+                "<fqdn> 'Domain name being validated, e.g. \"www.domain.com\" or \"*.domain.com\"'
+                <key_authorization> 'Key authorization of the challenge, its SHA-256 digest is the value of the TXT record'
+                --digest 'The key authorization is already the digest, as given by certbot (CERTBOT_VALIDATION) or acme.sh'")
+            .args(&propagation_args()))
+        .subcommand(SubCommand::with_name("providers")
+            .about("List the DNS and IP address providers available, with the settings of DNS \
+                    providers in the profiles of the config file"))
+        .subcommand(SubCommand::with_name("detect")
+            .about("Print your current IP address, without any DNS change")
+            .arg(ip_provider_arg(&ip_provider_help))
+            .args(&ip_detection_args()))
+        .get_matches_from(args);
+
+    let (subcommand, maybe_sub_matches) = matches.subcommand();
+    // A subcommand is required, see AppSettings::SubcommandRequiredElseHelp
+    let sub_matches = maybe_sub_matches.unwrap();
+
+    init_logging(sub_matches);
+
+    if flat {
+        warn!("No subcommand given, running 'update': use 'gdu update' instead, the command line \
+               without subcommand is deprecated");
+    }
 
-    // Init logger
-    let format = |record: &LogRecord| {
-        let t = time::now();
-        format!("{},{:03} - {} - {}",
-                time::strftime("%Y-%m-%d %H:%M:%S", &t).unwrap(),
-                t.tm_nsec / 1000_000,
-                record.level(),
-                record.args())
+    let output = match sub_matches.value_of("output") {
+        Some(_) => value_t_or_exit!(sub_matches.value_of("output"), OutputFormat),
+        None if sub_matches.is_present("json") => OutputFormat::Json,
+        None => OutputFormat::Text,
     };
+    debug!("Output format: {:?}", output);
 
-    // Init logging to DEBUG only if user requires it
-    let log_level_filter = match matches.occurrences_of("verbose") {
-        0 => LogLevelFilter::Info,
-        1 => {
-            println!("Verbose mode");
-            LogLevelFilter::Debug
+    let report = Report::new(output, subcommand);
+
+    let command = match subcommand {
+        "detect" => Command::Detect(read_ip_detector(sub_matches)),
+        "providers" => Command::Providers,
+        "status" => {
+            Command::Status(read_config(sub_matches),
+                            StatusOptions {
+                                record_name: read_record_name(sub_matches),
+                                ip_detector: read_ip_detector(sub_matches),
+                            })
+        }
+        "list" => Command::List(read_config(sub_matches)),
+        "export" => {
+            let format = value_t_or_exit!(sub_matches.value_of("format"), ExportFormat);
+            debug!("Export format: {:?}", format);
+
+            Command::Export(read_config(sub_matches), format)
+        }
+        "sync" => {
+            let zonefile = sub_matches.value_of("zonefile").unwrap();
+            debug!("Zonefile: {}", zonefile);
+
+            Command::Sync(read_config(sub_matches), zonefile.to_owned())
+        }
+        "prefix" => {
+            let source = match sub_matches.value_of("prefix") {
+                Some(_) => {
+                    PrefixSource::Address(value_t_or_exit!(sub_matches.value_of("prefix"),
+                                                           Ipv6Addr))
+                }
+                None => PrefixSource::Detect(read_ip_detector(sub_matches)),
+            };
+
+            let prefix_length = value_t_or_exit!(sub_matches.value_of("prefix-length"), u8);
+            debug!("Prefix length: {}", prefix_length);
+
+            let hosts = values_t_or_exit!(sub_matches.values_of("host"), HostSuffix);
+            debug!("Hosts: {:?}", hosts);
+
+            Command::Prefix(read_config(sub_matches),
+                            PrefixOptions {
+                                source: source,
+                                prefix_length: prefix_length,
+                                hosts: hosts,
+                                ttl: read_ttl(sub_matches),
+                            })
+        }
+        "set" => {
+            let record_type = value_t_or_exit!(sub_matches.value_of("type"), RecordType);
+            debug!("Record type: {:?}", record_type);
+
+            let value = sub_matches.value_of("value").unwrap();
+            debug!("Record value: {}", value);
+
+            Command::Set(read_config(sub_matches),
+                         SetOptions {
+                             record_name: read_record_name(sub_matches),
+                             record_type: record_type,
+                             value: value.to_owned(),
+                             ttl: read_ttl(sub_matches),
+                         })
+        }
+        "delete" => {
+            let record_type = value_t_or_exit!(sub_matches.value_of("type"), RecordType);
+            debug!("Record type: {:?}", record_type);
+
+            Command::Delete(read_config(sub_matches),
+                            DeleteOptions {
+                                record_name: read_record_name(sub_matches),
+                                record_type: record_type,
+                            })
+        }
+        "acme" => {
+            let action = value_t_or_exit!(sub_matches.value_of("action"), AcmeAction);
+            debug!("ACME action: {:?}", action);
+
+            let fqdn = sub_matches.value_of("fqdn").unwrap();
+            debug!("Domain name to validate: {}", fqdn);
+
+            Command::Acme(read_config(sub_matches),
+                          AcmeOptions {
+                              action: action,
+                              fqdn: fqdn.to_owned(),
+                              key_authorization: sub_matches.value_of("key_authorization")
+                                  .unwrap()
+                                  .to_owned(),
+                              digest: sub_matches.is_present("digest"),
+                              propagation: read_propagation(sub_matches),
+                          })
         }
-        2 | _ => {
-            println!("More verbose mode");
-            LogLevelFilter::Trace
+        "update" | _ => {
+            let ttl = read_ttl(sub_matches);
+
+            let force = sub_matches.is_present("force");
+            debug!("Force: {}", force);
+
+            let verify = match sub_matches.is_present("verify") {
+                true => Some(read_propagation(sub_matches)),
+                false => None,
+            };
+
+            let daemon = match sub_matches.value_of("interval") {
+                Some(_) => {
+                    let interval = value_t_or_exit!(sub_matches.value_of("interval"), u64);
+                    if interval == 0 {
+                        clap::Error::with_description("--interval must be at least 1 second",
+                                                      clap::ErrorKind::InvalidValue)
+                            .exit()
+                    }
+                    debug!("Daemon mode, interval: {} seconds", interval);
+
+                    let metrics_listen = match sub_matches.value_of("metrics-listen") {
+                        Some(_) => {
+                            Some(value_t_or_exit!(sub_matches.value_of("metrics-listen"),
+                                                  SocketAddr))
+                        }
+                        None => None,
+                    };
+
+                    Some(DaemonOptions {
+                        interval: interval,
+                        metrics_listen: metrics_listen,
+                    })
+                }
+                None => None,
+            };
+
+            let options = UpdateOptions {
+                // Set for each record of the config file if not given
+                record_name: sub_matches.value_of("record-name").unwrap_or("").to_string(),
+                ip_detector: read_ip_detector(sub_matches),
+                ttl: ttl,
+                force: force,
+                verify: verify,
+                detailed_exit_codes: sub_matches.is_present("detailed-exitcodes"),
+                on_change: sub_matches.value_of("on-change").map(|command| command.to_string()),
+                webhook: sub_matches.value_of("webhook").map(|url| url.to_string()),
+                daemon: daemon,
+            };
+
+            match sub_matches.is_present("record-name") {
+                true => {
+                    debug!("Using record name: {}", options.record_name);
+                    Command::Update(read_config(sub_matches), options)
+                }
+                false => Command::UpdateAll(read_managed_records(sub_matches), options),
+            }
         }
     };
 
-    let mut builder = LogBuilder::new();
-    builder.format(format).filter(None, log_level_filter);
-    builder.init().unwrap();
+    (command, report)
+}
 
-    // Read parameters
-    let apikey = matches.value_of("apikey").unwrap();
-    debug!("Using apikey: {}", apikey);
+/// Reads DNS provider parameters, shared by all commands dealing with DNS
+fn read_config(matches: &ArgMatches) -> Config {
+    let (provider, settings) = match matches.value_of("profile") {
+        Some(name) => {
+            let config_file = read_config_file(matches);
+            match config_file.profile(name) {
+                Ok(profile) => (profile.provider, profile.settings.clone()),
+                Err(err) => {
+                    clap::Error::with_description(&err.to_string(), clap::ErrorKind::InvalidValue)
+                        .exit()
+                }
+            }
+        }
+        None => {
+            let provider = value_t_or_exit!(Some(matches.value_of("provider").unwrap_or("gandi")),
+                                            ProviderType);
+            (provider, read_provider_settings(matches, provider))
+        }
+    };
+    debug!("DNS provider: {}", provider);
 
-    let domain = matches.value_of("domain").unwrap();
+    let domain = match matches.value_of("domain") {
+        Some(domain) => domain,
+        None => {
+            clap::Error::with_description("--domain is required with --record-name",
+                                          clap::ErrorKind::MissingRequiredArgument)
+                .exit()
+        }
+    };
     debug!("Using domain: {}", domain);
 
-    let record_name = matches.value_of("record-name").unwrap();
-    debug!("Using record name: {}", record_name);
+    let dry_run = matches.is_present("dry-run");
+    debug!("Dry run: {}", dry_run);
+
+    Config {
+        provider: provider,
+        settings: settings,
+        http: read_http_settings(matches),
+        domain: domain.to_owned(),
+        dry_run: dry_run,
+        keep_versions: read_keep_versions(matches),
+    }
+}
+
+/// Reads the settings of the DNS provider set on the command line, its default settings if none
+/// is set
+fn read_provider_settings(matches: &ArgMatches, provider: ProviderType) -> toml::Table {
+    let mut settings = toml::Table::new();
+
+    for setting_arg in setting_args() {
+        if let Some(value) = matches.value_of(setting_arg.setting) {
+            if !provider.has_setting(setting_arg.setting) {
+                clap::Error::with_description(&format!("--{} is not a setting of DNS provider {}",
+                                                       setting_arg.long,
+                                                       provider),
+                                              clap::ErrorKind::ArgumentConflict)
+                    .exit()
+            }
+            settings.insert(setting_arg.setting.to_string(),
+                            toml::Value::String(value.to_string()));
+        }
+    }
+
+    match settings.is_empty() {
+        true => provider.default_settings(),
+        false => settings,
+    }
+}
+
+/// Reads the records of the config file, and the credentials of their profiles
+fn read_managed_records(matches: &ArgMatches) -> ManagedRecords {
+    let config_file = read_config_file(matches);
+
+    if config_file.records.is_empty() {
+        clap::Error::with_description("No record declared in the config file, --record-name is \
+                                       required",
+                                      clap::ErrorKind::MissingRequiredArgument)
+            .exit()
+    }
 
     let dry_run = matches.is_present("dry-run");
     debug!("Dry run: {}", dry_run);
 
-    let force = matches.is_present("force");
-    debug!("Force: {}", force);
+    let keep_versions = read_keep_versions(matches);
+    let http = read_http_settings(matches);
+
+    let mut profiles = BTreeMap::new();
+    for (name, profile) in &config_file.profiles {
+        debug!("Profile '{}': {}", name, profile.provider);
+
+        profiles.insert(name.clone(),
+                        Config {
+                            provider: profile.provider,
+                            settings: profile.settings.clone(),
+                            http: http.clone(),
+                            domain: String::new(),
+                            dry_run: dry_run,
+                            keep_versions: keep_versions,
+                        });
+    }
+
+    ManagedRecords {
+        profiles: profiles,
+        records: config_file.records,
+    }
+}
+
+fn read_config_file(matches: &ArgMatches) -> ConfigFile {
+    let path = matches.value_of("config").unwrap();
+    debug!("Config file: {}", path);
+
+    match ConfigFile::read(path) {
+        Ok(config_file) => config_file,
+        Err(err) => {
+            clap::Error::with_description(&format!("Failed to read config file '{}': {}",
+                                                   path,
+                                                   err),
+                                          clap::ErrorKind::InvalidValue)
+                .exit()
+        }
+    }
+}
+
+fn read_keep_versions(matches: &ArgMatches) -> Option<u16> {
+    let keep_versions = match matches.value_of("keep-versions") {
+        Some(_) => Some(value_t_or_exit!(matches.value_of("keep-versions"), u16)),
+        None => None,
+    };
+    debug!("Zone versions to keep: {:?}", keep_versions);
+    keep_versions
+}
 
+fn read_record_name(matches: &ArgMatches) -> String {
+    let record_name = matches.value_of("record-name").unwrap();
+    debug!("Using record name: {}", record_name);
+    record_name.to_owned()
+}
+
+fn read_ttl(matches: &ArgMatches) -> Option<u32> {
+    let ttl = match matches.value_of("ttl") {
+        Some(_) => Some(value_t_or_exit!(matches.value_of("ttl"), u32)),
+        None => None,
+    };
+    debug!("Record TTL: {:?}", ttl);
+    ttl
+}
+
+fn read_propagation(matches: &ArgMatches) -> PropagationOptions {
+    let resolver = match matches.value_of("resolver") {
+        Some(_) => {
+            let ip_addr = value_t_or_exit!(matches.value_of("resolver"), IpAddr);
+            Some(SocketAddr::new(ip_addr, 53))
+        }
+        None => None,
+    };
+    debug!("Resolver: {:?}", resolver);
+
+    let timeout = value_t_or_exit!(matches.value_of("propagation-timeout"), u64);
+    debug!("Propagation timeout: {}s", timeout);
+
+    PropagationOptions {
+        resolver: resolver,
+        timeout: timeout,
+    }
+}
+
+fn read_ip_detector(matches: &ArgMatches) -> IpDetector {
     let ip_provider = value_t_or_exit!(matches.value_of("ip_provider"), IpProvider);
-    debug!("IP address provider: {:?}", ip_provider);
+    debug!("IP address provider: {}", ip_provider);
 
-    Config {
-        apikey: apikey.to_owned(),
-        domain: domain.to_owned(),
-        record_name: record_name.to_owned(),
-        force: force,
-        ip_provider: ip_provider,
+    let family = match matches.value_of("ip-family") {
+        Some(_) => Some(value_t_or_exit!(matches.value_of("ip-family"), IpFamily)),
+        None => None,
+    };
+
+    let source = match (matches.value_of("source-address"), matches.value_of("interface")) {
+        (Some(_), _) => {
+            Some(Source::Address(value_t_or_exit!(matches.value_of("source-address"), IpAddr)))
+        }
+        (None, Some(interface)) => Some(Source::Interface(interface.to_string())),
+        (None, None) => None,
+    };
+
+    IpDetector {
+        provider: ip_provider,
+        http: read_http_settings(matches),
+        family: family,
+        source: source,
     }
 }
 
-fn main_with_errors(config: &Config) -> Result<()> {
+/// Installs the logger: level from -v or --quiet, refined per module by --log-filter or
+/// RUST_LOG, and format and destination of the logs
+fn init_logging(matches: &ArgMatches) {
+    // Init logging to DEBUG only if user requires it
+    let level = match matches.occurrences_of("verbose") {
+        _ if matches.is_present("quiet") => LogLevelFilter::Warn,
+        0 => LogLevelFilter::Info,
+        1 => LogLevelFilter::Debug,
+        2 | _ => LogLevelFilter::Trace,
+    };
+    let mut filter = Filter::new(level);
+    if matches.is_present("quiet") {
+        filter = filter.parse(&format!("{}=info", logging::CHANGES)).unwrap();
+    }
 
-    let my_ip = try!(config.ip_provider.get_my_ip_addr());
+    let spec = matches.value_of("log-filter")
+        .map(|spec| ("log filter", spec.to_string()))
+        .or_else(|| first_env_var(&["RUST_LOG"]));
+    if let Some((name, spec)) = spec {
+        filter = match filter.parse(&spec) {
+            Ok(filter) => filter,
+            Err(err) => {
+                clap::Error::with_description(&format!("Invalid {}: {}", name, err),
+                                              clap::ErrorKind::InvalidValue)
+                    .exit()
+            }
+        };
+    }
 
-    info!("My IP address: {:?}", my_ip);
+    let format = match matches.value_of("log-format") {
+        Some(_) => value_t_or_exit!(matches.value_of("log-format"), LogFormat),
+        None => LogFormat::Text,
+    };
+
+    // Logs are written on stderr by default, stdout is kept for the output
+    let destination = match matches.value_of("log-file") {
+        _ if matches.is_present("journal") => Destination::Journal,
+        Some(path) => {
+            let max_size = match matches.value_of("log-max-size") {
+                Some(_) => value_t_or_exit!(matches.value_of("log-max-size"), u64),
+                None => 10,
+            };
+            let max_size = match max_size.checked_mul(1024 * 1024) {
+                Some(max_size) => max_size,
+                None => {
+                    clap::Error::with_description(&format!("--log-max-size is too large: {} \
+                                                            megabytes",
+                                                           max_size),
+                                                  clap::ErrorKind::InvalidValue)
+                        .exit()
+                }
+            };
+            Destination::File(path.to_string(), max_size)
+        }
+        None => Destination::Stderr,
+    };
+
+    if let Err(err) = logging::init(filter.clone(), format, destination) {
+        clap::Error::with_description(&err, clap::ErrorKind::InvalidValue).exit()
+    }
+
+    debug!("Log filter: {:?}", filter);
+}
+
+/// Reads timeouts, retries, proxy and CA certificates of HTTP calls, shared by all commands
+fn read_http_settings(matches: &ArgMatches) -> HttpSettings {
+    let mut settings = HttpSettings::default();
+
+    if matches.is_present("connect-timeout") {
+        settings.connect_timeout =
+            Duration::from_secs(value_t_or_exit!(matches.value_of("connect-timeout"), u64));
+    }
+    if matches.is_present("timeout") {
+        settings.timeout = Duration::from_secs(value_t_or_exit!(matches.value_of("timeout"), u64));
+    }
+    if matches.is_present("retries") {
+        settings.retries = value_t_or_exit!(matches.value_of("retries"), u32);
+    }
+    if matches.is_present("retry-backoff") {
+        settings.backoff =
+            Duration::from_millis(value_t_or_exit!(matches.value_of("retry-backoff"), u64));
+    }
+
+    if matches.is_present("proxy") {
+        let proxy = value_t_or_exit!(matches.value_of("proxy"), Proxy);
+        settings.https_proxy = Some(proxy.clone());
+        settings.http_proxy = Some(proxy);
+    } else {
+        settings.https_proxy = read_proxy_env(&http::HTTPS_PROXY_ENV);
+        settings.http_proxy = read_proxy_env(&http::HTTP_PROXY_ENV);
+    }
+    settings.no_proxy = match matches.value_of("no-proxy") {
+        Some(hosts) => http::parse_no_proxy(hosts),
+        None => {
+            first_env_var(&http::NO_PROXY_ENV)
+                .map(|(_, hosts)| http::parse_no_proxy(&hosts))
+                .unwrap_or_default()
+        }
+    };
+    settings.ca_file = matches.value_of("ca-file").map(|path| path.to_string());
+
+    if let Err(err) = settings.validate() {
+        clap::Error::with_description(&err, clap::ErrorKind::InvalidValue).exit()
+    }
+
+    debug!("HTTP settings: {:?}", settings);
+    settings
+}
+
+/// Proxy of the first of the environment variables `names` which is set
+fn read_proxy_env(names: &[&'static str]) -> Option<Proxy> {
+    first_env_var(names).map(|(name, value)| {
+        match value.parse::<Proxy>() {
+            Ok(proxy) => proxy,
+            Err(err) => {
+                clap::Error::with_description(&format!("Invalid {}: {}", name, err),
+                                              clap::ErrorKind::InvalidValue)
+                    .exit()
+            }
+        }
+    })
+}
+
+/// First of the environment variables `names` which is set and not empty, with its value
+fn first_env_var(names: &[&'static str]) -> Option<(&'static str, String)> {
+    names.iter()
+        .filter_map(|name| env::var(name).ok().map(|value| (*name, value)))
+        .find(|&(_, ref value)| !value.is_empty())
+}
 
-    let mut dns_provider = DNSProviderFactory::build(config);
+fn main_with_errors(dns_provider: &mut DNSProvider,
+                    config: &Config,
+                    options: &UpdateOptions,
+                    my_ip: IpAddr,
+                    report: &mut Report)
+                    -> Result<Outcome> {
+
+    let start = Instant::now();
+    try!(init_domain(dns_provider, config, report));
 
     match my_ip {
         std::net::IpAddr::V6(_) if !dns_provider.handle_ipv6_addr() => {
@@ -150,26 +1039,830 @@ fn main_with_errors(config: &Config) -> Result<()> {
         _ => (),
     }
 
-    let record = Record::new(&config.record_name, &my_ip);
-
-    try!(dns_provider.init(&config.domain));
+    let record = Record::new(&options.record_name, &my_ip, options.ttl);
+    let value = RecordValue::Ip(my_ip);
 
     let maybe_checked = try!(dns_provider.is_record_already_declared(&record));
 
-    match maybe_checked {
-        Some(ip_addr) => {
-            debug!("Record already declared, with IP address: {}", &ip_addr);
+    let mut changes = ChangeSet::new();
+    let mut current = Vec::new();
+
+    let outcome = match maybe_checked {
+        Some(declared) => {
+            debug!("Record already declared, with IP address: {} and TTL: {}",
+                   &declared.value,
+                   &declared.ttl);
 
-            if !config.force && (&ip_addr == &my_ip) {
-                info!("IP address not modified, no record to update");
-                Ok(())
+            if !options.force && declared.is_up_to_date(&record, &value) {
+                info!("IP address and TTL not modified, no record to update");
+                report.add_record(RecordReport {
+                    name: record.name.to_string(),
+                    type_: record.type_.to_string(),
+                    action: Some("unchanged".to_string()),
+                    previous_value: Some(declared.value.to_string()),
+                    value: Some(value.to_string()),
+                    ttl: Some(declared.ttl),
+                });
+                Outcome::Unchanged(my_ip)
             } else {
-                info!("Update record '{:?}' with IP address '{:?}'",
+                info!(target: logging::CHANGES,
+                      "Update record '{:?}' with IP address '{:?}'",
                       &record,
                       &my_ip);
-                Ok(try!(dns_provider.update_record(&record, &my_ip)))
+                current.push(declared_record(&record, &declared));
+                changes.update(record, value);
+                Outcome::Updated {
+                    previous: declared.value,
+                    ip_addr: my_ip,
+                }
+            }
+        }
+        None => {
+            info!(target: logging::CHANGES,
+                  "Create record '{:?}' with IP address '{:?}'",
+                  &record,
+                  &my_ip);
+            changes.create(record, value);
+            Outcome::Created(my_ip)
+        }
+    };
+
+    try!(commit(dns_provider, &changes, &current, report));
+    report.timing("dns_provider", start);
+
+    report.set_outcome(outcome.name());
+
+    Ok(outcome)
+}
+
+fn update(config: &Config, options: &UpdateOptions, report: &mut Report) -> Result<i32> {
+    let my_ip = try!(detect_ip_addr(&options.ip_detector, report));
+
+    info!("My IP address: {:?}", my_ip);
+
+    let mut dns_provider = try!(DNSProviderFactory::build(config));
+
+    update_record(&mut *dns_provider, config, options, my_ip, report)
+        .map(|(_, exit_code)| exit_code)
+}
+
+/// Updates all the records of the config file, with one DNS provider per profile.
+///
+/// A failing record doesn't stop the update of the next ones, the failure of the highest exit
+/// code is returned once all of them are updated.
+fn update_all(records: &ManagedRecords,
+              options: &UpdateOptions,
+              report: &mut Report)
+              -> Result<i32> {
+    // Records leaving from the same source share the same IP address
+    let mut detected_ips: Vec<(Option<Source>, IpAddr)> = Vec::new();
+
+    let mut dns_providers = BTreeMap::new();
+    for (name, config) in &records.profiles {
+        let dns_provider = try!(DNSProviderFactory::build(config).map_err(|err| match err {
+            Error::Credentials(label) => {
+                Error::Credentials(format!("Profile '{}': {}", name, label))
+            }
+            err => err,
+        }));
+        dns_providers.insert(&name[..], dns_provider);
+    }
+
+    let mut exit_code = EXIT_OK;
+    let mut changed = false;
+    let mut failure: Option<Error> = None;
+
+    for record in &records.records {
+        info!("Record '{}' of '{}', with profile '{}'",
+              record.name,
+              record.domain,
+              record.profile);
+
+        logging::clear_fields();
+        let mut record_report = Report::new(report.format(), "update");
+        let dns_provider = dns_providers.get_mut(&record.profile[..]).unwrap();
+
+        match update_managed_record(&mut **dns_provider,
+                                    record,
+                                    records,
+                                    options,
+                                    &mut detected_ips,
+                                    &mut record_report) {
+            Ok((outcome, record_exit_code)) => {
+                changed = changed || outcome.name() != "unchanged";
+                if exit_code == EXIT_OK || record_exit_code == EXIT_NOT_PROPAGATED {
+                    exit_code = record_exit_code;
+                }
+                report.add_run(record_report, record_exit_code, None);
+            }
+            Err(err) => {
+                error!("Update of record '{}' of '{}' failed: {}",
+                       record.name,
+                       record.domain,
+                       err);
+                report.add_run(record_report,
+                               failure_exit_code(&err),
+                               Some((failure_kind(&err), err.to_string())));
+
+                let worse = match failure {
+                    Some(ref failure) => failure_exit_code(&err) > failure_exit_code(failure),
+                    None => true,
+                };
+                if worse {
+                    failure = Some(err);
+                }
             }
         }
-        None => Ok(try!(dns_provider.create_record(&record, &my_ip))),
     }
+
+    report.set_outcome(match changed {
+        true => "updated",
+        false => "unchanged",
+    });
+
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(exit_code),
+    }
+}
+
+/// Updates a record of the config file, with the IP address of its source, detected once
+fn update_managed_record(dns_provider: &mut DNSProvider,
+                         record: &ManagedRecord,
+                         records: &ManagedRecords,
+                         options: &UpdateOptions,
+                         detected_ips: &mut Vec<(Option<Source>, IpAddr)>,
+                         report: &mut Report)
+                         -> Result<(Outcome, i32)> {
+    let mut ip_detector = options.ip_detector.clone();
+    if record.source.is_some() {
+        ip_detector.source = record.source.clone();
+    }
+
+    let known_ip = detected_ips.iter()
+        .find(|&&(ref source, _)| *source == ip_detector.source)
+        .map(|&(_, ip_addr)| ip_addr);
+    let my_ip = match known_ip {
+        Some(ip_addr) => {
+            report.set_ip_address(&ip_addr);
+            ip_addr
+        }
+        None => {
+            let ip_addr = try!(detect_ip_addr(&ip_detector, report));
+            info!("My IP address: {:?}", ip_addr);
+            detected_ips.push((ip_detector.source.clone(), ip_addr));
+            ip_addr
+        }
+    };
+
+    let mut config = records.profiles[&record.profile].clone();
+    config.domain = record.domain.clone();
+
+    let mut record_options = options.clone();
+    record_options.record_name = record.name.clone();
+    record_options.ttl = record.ttl.or(options.ttl);
+
+    update_record(dns_provider, &config, &record_options, my_ip, report)
+}
+
+/// Updates the record with `my_ip`, then runs the hooks and verifies the propagation
+fn update_record(dns_provider: &mut DNSProvider,
+                 config: &Config,
+                 options: &UpdateOptions,
+                 my_ip: IpAddr,
+                 report: &mut Report)
+                 -> Result<(Outcome, i32)> {
+    logging::set_field("RECORD", &options.record_name);
+    logging::set_field("IP", &my_ip.to_string());
+
+    let outcome = try!(main_with_errors(dns_provider, config, options, my_ip, report));
+
+    run_hooks(config, options, &outcome, report);
+
+    let exit_code = match try!(verify(config, options, outcome.ip_addr(), report)) {
+        Some(false) => EXIT_NOT_PROPAGATED,
+        _ => {
+            match outcome {
+                Outcome::Updated { .. } if options.detailed_exit_codes => EXIT_UPDATED,
+                Outcome::Created(_) if options.detailed_exit_codes => EXIT_CREATED,
+                _ => EXIT_OK,
+            }
+        }
+    };
+
+    Ok((outcome, exit_code))
+}
+
+/// Runs the update every `interval` seconds, forever. Each run has its own report, and
+/// failures don't stop the loop.
+fn daemon<F>(daemon_options: &DaemonOptions, report: &Report, run: F) -> Result<i32>
+    where F: Fn(&mut Report) -> Result<i32>
+{
+
+    let metrics = Arc::new(Mutex::new(Metrics::new()));
+
+    // Kept until the end of the process
+    let _listening = match daemon_options.metrics_listen {
+        Some(address) => Some(try!(metrics::serve(address, metrics.clone()))),
+        None => None,
+    };
+
+    info!("Update record every {} seconds", daemon_options.interval);
+
+    let watchdog_interval = systemd::watchdog_interval();
+    systemd::notify("READY=1\nSTATUS=Starting");
+
+    loop {
+        logging::clear_fields();
+        let mut run_report = Report::new(report.format(), "update");
+        let result = run(&mut run_report);
+
+        metrics.lock().unwrap().record_run(&run_report, &result, &metrics::take_calls());
+        let status = metrics.lock().unwrap().status();
+
+        match result {
+            Ok(exit_code) => {
+                systemd::notify(&format!("STATUS={}", status));
+                run_report.finish(exit_code, None)
+            }
+            Err(err) => {
+                error!("Update failed: {}", err);
+                systemd::notify(&format!("STATUS={}, last update failed: {}", status, err));
+                run_report.finish(failure_exit_code(&err),
+                                  Some((failure_kind(&err), err.to_string())));
+            }
+        }
+
+        sleep(Duration::from_secs(daemon_options.interval), watchdog_interval);
+    }
+}
+
+/// Sleeps for `duration`, pinging the systemd watchdog twice per `watchdog_interval` if
+/// enabled. A run stuck for longer than the interval isn't pinged for, so systemd restarts gdu.
+fn sleep(duration: Duration, watchdog_interval: Option<Duration>) {
+    let watchdog_interval = match watchdog_interval {
+        Some(watchdog_interval) => watchdog_interval,
+        None => {
+            thread::sleep(duration);
+            return;
+        }
+    };
+    let ping_interval = cmp::max(watchdog_interval / 2, Duration::from_millis(1));
+
+    let mut remaining = duration;
+    loop {
+        systemd::notify("WATCHDOG=1");
+        if remaining == Duration::from_secs(0) {
+            return;
+        }
+
+        let step = cmp::min(remaining, ping_interval);
+        thread::sleep(step);
+        remaining = remaining - step;
+    }
+}
+
+/// Runs the `--on-change` command and calls the `--webhook` if the record has changed. The
+/// record is already committed, failures are only reported.
+fn run_hooks(config: &Config, options: &UpdateOptions, outcome: &Outcome, report: &mut Report) {
+    if config.dry_run {
+        return;
+    }
+
+    let change = match hooks::IpChange::from_outcome(&config.domain,
+                                                     &options.record_name,
+                                                     outcome) {
+        Some(change) => change,
+        None => return,
+    };
+
+    if let Some(ref command) = options.on_change {
+        let result = hooks::run_command(command, &change);
+        report_hook(report, "on_change", result);
+    }
+
+    if let Some(ref url) = options.webhook {
+        let result = hooks::post_webhook(url, &change, &config.http);
+        report_hook(report, "webhook", result);
+    }
+}
+
+fn report_hook(report: &mut Report, hook: &str, result: Result<()>) {
+    if let Err(ref err) = result {
+        error!("{}, DNS record kept", err);
+    }
+    report.add_hook(hook, result.err().map(|err| err.to_string()));
+}
+
+/// Waits until the authoritative nameservers serve the record if required, returns whether
+/// all of them do
+fn verify(config: &Config,
+          options: &UpdateOptions,
+          my_ip: &IpAddr,
+          report: &mut Report)
+          -> Result<Option<bool>> {
+
+    let propagation = match options.verify {
+        Some(ref propagation) if !config.dry_run => propagation,
+        Some(_) => {
+            info!("Dry run, propagation not verified");
+            return Ok(None);
+        }
+        None => return Ok(None),
+    };
+
+    let query_type = match my_ip {
+        &IpAddr::V4(_) => QueryType::A,
+        &IpAddr::V6(_) => QueryType::AAAA,
+    };
+    let expected = Answer::from(*my_ip);
+
+    let start = Instant::now();
+    let resolver = try!(propagation::resolver(propagation.resolver));
+    let statuses = try!(propagation::check(&resolver,
+                                           &config.domain,
+                                           &options.record_name,
+                                           query_type,
+                                           |answers| answers == &[expected.clone()],
+                                           Duration::from_secs(propagation.timeout)));
+    report.timing("propagation", start);
+
+    for status in &statuses {
+        report.println(&status.to_string());
+        report.add_nameserver(status);
+    }
+
+    if statuses.iter().all(|status| status.visible) {
+        info!("Record served by all {} authoritative nameservers", statuses.len());
+        Ok(Some(true))
+    } else {
+        error!("Record not served by all authoritative nameservers after {} seconds",
+               propagation.timeout);
+        Ok(Some(false))
+    }
+}
+
+fn failure_kind(err: &Error) -> &'static str {
+    match failure_exit_code(err) {
+        EXIT_IP_DETECTION_FAILED => "ip_detection",
+        EXIT_AUTH_FAILED => "auth",
+        EXIT_NETWORK_FAILED => "network",
+        _ => "other",
+    }
+}
+
+fn failure_exit_code(err: &Error) -> i32 {
+    match err {
+        &Error::IpDetection(_) => EXIT_IP_DETECTION_FAILED,
+        &Error::Auth(_) => EXIT_AUTH_FAILED,
+        &Error::Http(_) | &Error::HttpStatus(_) | &Error::Dns(_) => EXIT_NETWORK_FAILED,
+        &Error::Io(ref err) if is_network_error(err) => EXIT_NETWORK_FAILED,
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// Whether the I/O error is a socket one, e.g. a connection refused or timed out, rather than
+/// a local one, e.g. a missing file
+fn is_network_error(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::NotConnected |
+        io::ErrorKind::AddrNotAvailable |
+        io::ErrorKind::BrokenPipe |
+        io::ErrorKind::TimedOut => true,
+        _ => is_unreachable(err),
+    }
+}
+
+#[cfg(unix)]
+fn is_unreachable(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(code) => {
+            [libc::ENETDOWN, libc::ENETUNREACH, libc::EHOSTDOWN, libc::EHOSTUNREACH].contains(&code)
+        }
+        None => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_unreachable(_: &io::Error) -> bool {
+    false
+}
+
+/// Gets the current IP address, any failure is an `Error::IpDetection`
+fn detect_ip_addr(ip_detector: &IpDetector, report: &mut Report) -> Result<IpAddr> {
+    let start = Instant::now();
+
+    let my_ip = try!(ip_detector.get_my_ip_addr()
+        .map_err(|err| Error::IpDetection(Box::new(err))));
+
+    report.timing("ip_detection", start);
+    report.set_ip_address(&my_ip);
+    logging::set_field("IP", &my_ip.to_string());
+
+    Ok(my_ip)
+}
+
+/// Builds the DNS provider of the domain, ready to be used
+fn init_provider<'a>(config: &'a Config, report: &mut Report) -> Result<Box<DNSProvider + 'a>> {
+    let mut dns_provider = try!(DNSProviderFactory::build(config));
+
+    try!(init_domain(&mut *dns_provider, config, report));
+
+    Ok(dns_provider)
+}
+
+/// Makes the DNS provider work on the domain of `config`
+fn init_domain(dns_provider: &mut DNSProvider, config: &Config, report: &mut Report) -> Result<()> {
+    report.set_provider(&config.provider.to_string(), &config.domain, config.dry_run);
+    logging::set_field("PROVIDER", &config.provider.to_string());
+
+    dns_provider.init(&config.domain)
+}
+
+/// Commits the changes and reports them, `current` records of the zone give their previous
+/// values
+fn commit(dns_provider: &DNSProvider,
+          changes: &ChangeSet,
+          current: &[ZoneRecord],
+          report: &mut Report)
+          -> Result<()> {
+
+    let zone_version = try!(dns_provider.commit(changes));
+
+    for change in changes.iter() {
+        report.add_record(RecordReport::from_change(change, current));
+    }
+    report.set_zone_version(zone_version);
+
+    Ok(())
+}
+
+fn detect(ip_detector: &IpDetector, report: &mut Report) -> Result<i32> {
+    let my_ip = try!(detect_ip_addr(ip_detector, report));
+
+    report.println(&my_ip.to_string());
+
+    Ok(EXIT_OK)
+}
+
+fn providers(report: &Report) -> Result<i32> {
+    print_registry("DNS providers", &dns::DNS_PROVIDERS, report);
+    report.println("");
+    print_registry("IP address providers", &myip::IP_PROVIDERS, report);
+
+    Ok(EXIT_OK)
+}
+
+/// Prints the name and description of each provider, then its settings if any
+fn print_registry<B: 'static>(title: &str, registry: &Registry<B>, report: &Report) {
+    report.println(&format!("{}:", title));
+
+    for provider in registry.providers {
+        report.println(&format!("  {:15} {}", provider.name, provider.description));
+        for &(setting, description) in provider.settings {
+            let line = format!("  {:15}   {:16} {}", "", setting, description);
+            match provider.defaults.iter().find(|&&(name, _)| name == setting) {
+                Some(&(_, value)) => {
+                    report.println(&format!("{}, {} if no setting is given", line, value))
+                }
+                None => report.println(&line),
+            }
+        }
+    }
+}
+
+fn status(config: &Config, options: &StatusOptions, report: &mut Report) -> Result<i32> {
+
+    let my_ip = try!(detect_ip_addr(&options.ip_detector, report));
+
+    info!("My IP address: {:?}", my_ip);
+
+    let record = Record::new(&options.record_name, &my_ip, None);
+
+    let start = Instant::now();
+    let dns_provider = try!(init_provider(config, report));
+
+    let maybe_declared = try!(dns_provider.is_record_already_declared(&record));
+    report.timing("dns_provider", start);
+
+    if let Some(ref declared) = maybe_declared {
+        report.add_record(RecordReport {
+            name: record.name.to_string(),
+            type_: record.type_.to_string(),
+            action: None,
+            previous_value: None,
+            value: Some(declared.value.to_string()),
+            ttl: Some(declared.ttl),
+        });
+    }
+
+    match maybe_declared {
+        Some(ref declared) if declared.is_up_to_date(&record, &RecordValue::Ip(my_ip)) => {
+            report.println(&format!("{} {}: {} (TTL {}), up to date",
+                                    record.name,
+                                    record.type_,
+                                    declared.value,
+                                    declared.ttl));
+            report.set_outcome("up_to_date");
+            Ok(EXIT_OK)
+        }
+        Some(declared) => {
+            report.println(&format!("{} {}: {} (TTL {}), outdated, current IP address is {}",
+                                    record.name,
+                                    record.type_,
+                                    declared.value,
+                                    declared.ttl,
+                                    my_ip));
+            report.set_outcome("outdated");
+            Ok(EXIT_OUTDATED)
+        }
+        None => {
+            report.println(&format!("{} {}: not declared, current IP address is {}",
+                                    record.name,
+                                    record.type_,
+                                    my_ip));
+            report.set_outcome("not_declared");
+            Ok(EXIT_NOT_FOUND)
+        }
+    }
+}
+
+fn list(config: &Config, report: &mut Report) -> Result<i32> {
+
+    let start = Instant::now();
+    let dns_provider = try!(init_provider(config, report));
+
+    for record in try!(dns_provider.list_records()) {
+        report.println(&format!("{}\t{}\t{}\t{}",
+                                record.name,
+                                record.ttl,
+                                record.type_,
+                                record.value));
+        report.add_record(RecordReport::from_zone_record(&record));
+    }
+    report.timing("dns_provider", start);
+
+    Ok(EXIT_OK)
+}
+
+fn export(config: &Config, format: &ExportFormat, report: &mut Report) -> Result<i32> {
+
+    let start = Instant::now();
+    let dns_provider = try!(init_provider(config, report));
+
+    let records = try!(dns_provider.list_records());
+    report.timing("dns_provider", start);
+
+    // Exported records are part of the result document with the JSON output
+    match report.format() {
+        OutputFormat::Text => {
+            let stdout = std::io::stdout();
+            try!(export::export(&config.domain, &records, format, &mut stdout.lock()));
+        }
+        OutputFormat::Json => {
+            for record in &records {
+                report.add_record(RecordReport::from_zone_record(record));
+            }
+        }
+    }
+
+    Ok(EXIT_OK)
+}
+
+fn sync(config: &Config, zonefile: &str, report: &mut Report) -> Result<i32> {
+
+    let mut input = String::new();
+    try!(try!(File::open(zonefile)).read_to_string(&mut input));
+
+    let desired = try!(zonefile::parse(&input, &config.domain));
+
+    let start = Instant::now();
+    let dns_provider = try!(init_provider(config, report));
+
+    let current = try!(dns_provider.list_records());
+
+    let changes = try!(sync::diff(&current, &desired));
+
+    if changes.is_empty() {
+        info!("Zone already in sync with '{}', no record to update", zonefile);
+    }
+
+    for change in changes.iter() {
+        report.println(&change.to_string());
+    }
+
+    try!(commit(&*dns_provider, &changes, &current, report));
+    report.timing("dns_provider", start);
+
+    Ok(EXIT_OK)
+}
+
+fn prefix(config: &Config, options: &PrefixOptions, report: &mut Report) -> Result<i32> {
+
+    let prefix = match options.source {
+        PrefixSource::Address(ip_addr) => ip_addr,
+        PrefixSource::Detect(ref ip_detector) => {
+            match try!(detect_ip_addr(ip_detector, report)) {
+                IpAddr::V6(ip_addr) => ip_addr,
+                IpAddr::V4(ip_addr) => {
+                    return Err(Error::InvalidRecord(format!("An IPv6 address is required to \
+                                                             derive the prefix, got {}",
+                                                            ip_addr)))
+                }
+            }
+        }
+    };
+
+    info!("IPv6 prefix: {}/{}", prefix, options.prefix_length);
+
+    let start = Instant::now();
+    let dns_provider = try!(init_provider(config, report));
+
+    let records = try!(dns_provider.list_records());
+
+    let current = prefix::current_records(&options.hosts, &records);
+    let desired = try!(prefix::desired_records(&options.hosts,
+                                               &prefix,
+                                               options.prefix_length,
+                                               options.ttl,
+                                               &current));
+
+    let changes = try!(sync::diff(&current, &desired));
+
+    if changes.is_empty() {
+        info!("AAAA records of all hosts already up to date, no record to update");
+    }
+
+    for change in changes.iter() {
+        report.println(&change.to_string());
+    }
+
+    try!(commit(&*dns_provider, &changes, &current, report));
+    report.timing("dns_provider", start);
+
+    Ok(EXIT_OK)
+}
+
+fn set(config: &Config, options: &SetOptions, report: &mut Report) -> Result<i32> {
+
+    let record = Record {
+        name: &options.record_name,
+        type_: options.record_type.clone(),
+        ttl: options.ttl,
+    };
+    let value = try!(RecordValue::parse(&options.record_type, &options.value));
+
+    let start = Instant::now();
+    let dns_provider = try!(init_provider(config, report));
+
+    let mut changes = ChangeSet::new();
+    let mut current = Vec::new();
+
+    match try!(dns_provider.is_record_already_declared(&record)) {
+        Some(ref declared) if declared.is_up_to_date(&record, &value) => {
+            info!("Value and TTL not modified, no record to update");
+        }
+        Some(declared) => {
+            info!(target: logging::CHANGES,
+                  "Update record '{:?}' with value '{}'",
+                  &record,
+                  &value);
+            current.push(declared_record(&record, &declared));
+            changes.update(record, value);
+        }
+        None => {
+            info!(target: logging::CHANGES,
+                  "Create record '{:?}' with value '{}'",
+                  &record,
+                  &value);
+            changes.create(record, value);
+        }
+    }
+
+    try!(commit(&*dns_provider, &changes, &current, report));
+    report.timing("dns_provider", start);
+
+    Ok(EXIT_OK)
+}
+
+fn delete(config: &Config, options: &DeleteOptions, report: &mut Report) -> Result<i32> {
+
+    let record = Record::of_type(&options.record_name, options.record_type.clone());
+
+    let start = Instant::now();
+    let dns_provider = try!(init_provider(config, report));
+
+    // The record is deleted with all its values, a single one is expected
+    let current = try!(dns_provider.list_records())
+        .into_iter()
+        .filter(|current| {
+            current.name == record.name &&
+            current.type_.to_uppercase() == record.type_.to_string()
+        })
+        .collect::<Vec<ZoneRecord>>();
+
+    match current.len() {
+        0 => {
+            info!("Record '{:?}' not declared, no record to delete", &record);
+            report.set_outcome("not_declared");
+            Ok(EXIT_NOT_FOUND)
+        }
+        1 => {
+            info!(target: logging::CHANGES,
+                  "Delete record '{:?}' with value '{}'",
+                  &record,
+                  &current[0].value);
+
+            let mut changes = ChangeSet::new();
+            changes.delete(record);
+
+            try!(commit(&*dns_provider, &changes, &current, report));
+            report.timing("dns_provider", start);
+            Ok(EXIT_OK)
+        }
+        values => {
+            Err(Error::InvalidRecord(format!("Record '{}' has {} {} values ({}), only a record \
+                                              with a single value can be deleted",
+                                             record.name,
+                                             values,
+                                             record.type_,
+                                             current.iter()
+                                                 .map(|current| &current.value[..])
+                                                 .collect::<Vec<&str>>()
+                                                 .join(", "))))
+        }
+    }
+}
+
+/// Zone record of a declared record, to report its previous value
+fn declared_record(record: &Record, declared: &DeclaredRecord) -> ZoneRecord {
+    ZoneRecord {
+        name: record.name.to_string(),
+        type_: record.type_.to_string(),
+        ttl: declared.ttl,
+        value: declared.value.to_string(),
+    }
+}
+
+fn acme(config: &Config, options: &AcmeOptions, report: &mut Report) -> Result<i32> {
+
+    let name = try!(acme::challenge_name(&options.fqdn, &config.domain));
+    let value = if options.digest {
+        options.key_authorization.clone()
+    } else {
+        acme::challenge_value(&options.key_authorization)
+    };
+
+    let start = Instant::now();
+    let dns_provider = try!(init_provider(config, report));
+
+    let current = try!(dns_provider.list_records());
+
+    let changes = match options.action {
+        AcmeAction::Present => try!(acme::present_changes(&name, &current, &value)),
+        AcmeAction::Cleanup => try!(acme::cleanup_changes(&name, &current, &value)),
+    };
+
+    if changes.is_empty() {
+        info!("Challenge record '{}' already up to date", name);
+    }
+
+    for change in changes.iter() {
+        info!(target: logging::CHANGES, "{}", change);
+    }
+
+    try!(commit(&*dns_provider, &changes, &current, report));
+    report.timing("dns_provider", start);
+
+    if options.action == AcmeAction::Present && !config.dry_run {
+        let start = Instant::now();
+        let timeout = options.propagation.timeout;
+        let resolver = try!(propagation::resolver(options.propagation.resolver));
+        let statuses = try!(acme::wait_for_propagation(&config.domain,
+                                                       &name,
+                                                       &value,
+                                                       &resolver,
+                                                       Duration::from_secs(timeout)));
+        report.timing("propagation", start);
+
+        for status in &statuses {
+            info!("{}", status);
+            report.add_nameserver(status);
+        }
+
+        let outdated = statuses.iter().filter(|status| !status.visible).count();
+        if outdated > 0 {
+            error!("Challenge record '{}' not served by {} of {} nameservers after {} seconds",
+                   propagation::fqdn(&name, &config.domain),
+                   outdated,
+                   statuses.len(),
+                   timeout);
+            return Ok(EXIT_NOT_PROPAGATED);
+        }
+    }
+
+    Ok(EXIT_OK)
 }
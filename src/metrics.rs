@@ -0,0 +1,425 @@
+use dns::{ChangeSet, DeclaredRecord, DNSProvider, Record, RecordValue, ZoneRecord};
+use error::Result;
+use gandi;
+use hyper::header::ContentType;
+use hyper::server::{Listening, Request, Response, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use myip::GetMyIpAddr;
+use report::Report;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use time;
+
+// Prometheus metrics of the update runs in daemon mode, exposed over HTTP in the text
+// exposition format
+
+/// Calls to a provider, timed whether they succeed or fail
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Call {
+    IpDetection,
+    DNSProvider,
+}
+
+thread_local! {
+    /// Latencies of the calls made since the last `take_calls`, in seconds
+    static CALLS: RefCell<Vec<(Call, f64)>> = RefCell::new(Vec::new())
+}
+
+fn timed<T, F: FnOnce() -> T>(call: Call, f: F) -> T {
+    let start = Instant::now();
+    let result = f();
+
+    let elapsed = start.elapsed();
+    let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    CALLS.with(|calls| calls.borrow_mut().push((call, seconds)));
+
+    result
+}
+
+/// Latencies of the calls made since the last call, e.g. during an update run
+pub fn take_calls() -> Vec<(Call, f64)> {
+    CALLS.with(|calls| calls.borrow_mut().drain(..).collect())
+}
+
+/// An IP address provider whose calls are timed
+pub struct TimedIpProvider(pub Box<GetMyIpAddr<IpAddr>>);
+
+impl GetMyIpAddr<IpAddr> for TimedIpProvider {
+    fn get_my_ip_addr(&self) -> Result<IpAddr> {
+        timed(Call::IpDetection, || self.0.get_my_ip_addr())
+    }
+}
+
+/// A DNS provider whose calls are timed
+pub struct TimedDNSProvider<'a>(pub Box<DNSProvider + 'a>);
+
+impl<'a> DNSProvider for TimedDNSProvider<'a> {
+    fn init(&mut self, domain: &str) -> Result<()> {
+        let provider = &mut self.0;
+        timed(Call::DNSProvider, || provider.init(domain))
+    }
+
+    fn handle_ipv6_addr(&self) -> bool {
+        self.0.handle_ipv6_addr()
+    }
+
+    fn is_record_already_declared(&self, record: &Record) -> Result<Option<DeclaredRecord>> {
+        timed(Call::DNSProvider,
+              || self.0.is_record_already_declared(record))
+    }
+
+    fn list_records(&self) -> Result<Vec<ZoneRecord>> {
+        timed(Call::DNSProvider, || self.0.list_records())
+    }
+
+    fn commit(&self, changes: &ChangeSet) -> Result<Option<u16>> {
+        timed(Call::DNSProvider, || self.0.commit(changes))
+    }
+
+    fn update_record(&self, record: &Record, value: &RecordValue) -> Result<Option<u16>> {
+        timed(Call::DNSProvider, || self.0.update_record(record, value))
+    }
+
+    fn create_record(&self, record: &Record, value: &RecordValue) -> Result<Option<u16>> {
+        timed(Call::DNSProvider, || self.0.create_record(record, value))
+    }
+
+    fn delete_record(&self, record: &Record) -> Result<Option<u16>> {
+        timed(Call::DNSProvider, || self.0.delete_record(record))
+    }
+}
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Histogram {
+    /// Observations per bucket, not cumulative
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            counts: vec![0; LATENCY_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.counts[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.counts.iter()) {
+            cumulative += *count;
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative).unwrap();
+        }
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count).unwrap();
+        writeln!(out, "{}_sum {}", name, self.sum).unwrap();
+        writeln!(out, "{}_count {}", name, self.count).unwrap();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    ip_checks: u64,
+    ip_changes: u64,
+    /// Failed runs, by `Error` variant
+    errors: BTreeMap<&'static str, u64>,
+    published_ip: Option<IpAddr>,
+    /// Seconds since the epoch
+    last_sync: Option<u64>,
+    ip_detection_latency: Histogram,
+    dns_provider_latency: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            ip_checks: 0,
+            ip_changes: 0,
+            errors: BTreeMap::new(),
+            published_ip: None,
+            last_sync: None,
+            ip_detection_latency: Histogram::new(),
+            dns_provider_latency: Histogram::new(),
+        }
+    }
+
+    /// Accounts for an update run, from its report, result and the latencies of its `calls`
+    pub fn record_run<T>(&mut self, report: &Report, result: &Result<T>, calls: &[(Call, f64)]) {
+        self.ip_checks += 1;
+
+        for &(call, seconds) in calls {
+            match call {
+                Call::IpDetection => self.ip_detection_latency.observe(seconds),
+                Call::DNSProvider => self.dns_provider_latency.observe(seconds),
+            }
+        }
+
+        match result {
+            &Ok(_) => {
+                match report.outcome() {
+                    Some("updated") | Some("created") => self.ip_changes += 1,
+                    _ => (),
+                }
+                // Gauges of the last published IP address, kept by failed runs and dry runs
+                match report.ip_address() {
+                    Some(ip_addr) if !report.is_dry_run() => {
+                        self.published_ip = Some(ip_addr);
+                        self.last_sync = Some(now());
+                    }
+                    _ => (),
+                }
+            }
+            &Err(ref err) => *self.errors.entry(err.name()).or_insert(0) += 1,
+        }
+    }
+
+    /// One-line summary of the published IP address and of the last sync, e.g. for systemd
+    pub fn status(&self) -> String {
+        match (self.published_ip, self.last_sync) {
+            (Some(ip_addr), Some(last_sync)) => {
+                let last_sync = time::at_utc(time::Timespec::new(last_sync as i64, 0));
+                format!("IP address {}, last sync {} UTC",
+                        ip_addr,
+                        time::strftime("%Y-%m-%d %H:%M:%S", &last_sync).unwrap())
+            }
+            _ => "No IP address published yet".to_string(),
+        }
+    }
+
+    /// Text exposition format, `api_calls` being the number of calls to the DNS provider API
+    pub fn render(&self, api_calls: u64) -> String {
+        let mut out = String::new();
+
+        counter(&mut out,
+                "gdu_ip_checks_total",
+                "Checks of the current IP address",
+                self.ip_checks);
+        counter(&mut out,
+                "gdu_ip_changes_total",
+                "Records updated or created with a new IP address",
+                self.ip_changes);
+        counter(&mut out,
+                "gdu_provider_api_calls_total",
+                "Calls to the DNS provider API",
+                api_calls);
+
+        writeln!(out, "# HELP gdu_errors_total Failed runs, by error kind").unwrap();
+        writeln!(out, "# TYPE gdu_errors_total counter").unwrap();
+        for (kind, count) in &self.errors {
+            writeln!(out, "gdu_errors_total{{kind=\"{}\"}} {}", kind, count).unwrap();
+        }
+
+        writeln!(out,
+                 "# HELP gdu_published_ip IP address published in the record, as a label")
+            .unwrap();
+        writeln!(out, "# TYPE gdu_published_ip gauge").unwrap();
+        if let Some(ip_addr) = self.published_ip {
+            writeln!(out, "gdu_published_ip{{ip=\"{}\"}} 1", ip_addr).unwrap();
+        }
+
+        writeln!(out,
+                 "# HELP gdu_last_sync_timestamp_seconds Time of the last successful run")
+            .unwrap();
+        writeln!(out, "# TYPE gdu_last_sync_timestamp_seconds gauge").unwrap();
+        if let Some(last_sync) = self.last_sync {
+            writeln!(out, "gdu_last_sync_timestamp_seconds {}", last_sync).unwrap();
+        }
+
+        self.ip_detection_latency.render(&mut out,
+                                         "gdu_ip_detection_duration_seconds",
+                                         "Latency of the calls to the IP address provider");
+        self.dns_provider_latency.render(&mut out,
+                                         "gdu_dns_provider_duration_seconds",
+                                         "Latency of the calls to the DNS provider");
+
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} counter", name).unwrap();
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+/// Serves the metrics on `/metrics` at `address`, in the background
+pub fn serve(address: SocketAddr, metrics: Arc<Mutex<Metrics>>) -> Result<Listening> {
+    let server = try!(Server::http(address));
+
+    let listening = try!(server.handle(move |request: Request, mut response: Response| {
+        match request.uri {
+            RequestUri::AbsolutePath(ref path) if path == "/metrics" => {
+                let body = metrics.lock().unwrap().render(gandi::api_calls());
+                response.headers_mut()
+                    .set(ContentType("text/plain; version=0.0.4".parse().unwrap()));
+                if let Err(err) = response.send(body.as_bytes()) {
+                    warn!("Failed to send metrics: {}", err);
+                }
+            }
+            _ => *response.status_mut() = StatusCode::NotFound,
+        }
+    }));
+
+    info!("Metrics served on http://{}/metrics", listening.socket);
+    Ok(listening)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error;
+    use report::{OutputFormat, Report};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = Histogram::new();
+        histogram.observe(0.2);
+        histogram.observe(3.0);
+        histogram.observe(60.0);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "latency", "Latency");
+
+        assert!(out.contains("latency_bucket{le=\"0.1\"} 0\n"));
+        assert!(out.contains("latency_bucket{le=\"0.25\"} 1\n"));
+        assert!(out.contains("latency_bucket{le=\"5\"} 2\n"));
+        assert!(out.contains("latency_bucket{le=\"10\"} 2\n"));
+        assert!(out.contains("latency_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("latency_count 3\n"));
+    }
+
+    #[test]
+    fn runs() {
+        let mut metrics = Metrics::new();
+
+        let mut report = Report::new(OutputFormat::Text, "update");
+        report.set_provider("gandi", "example.com", false);
+        report.set_ip_address(&"100.3.5.5".parse().unwrap());
+        report.set_outcome("updated");
+        metrics.record_run(&report,
+                           &Ok(()),
+                           &[(Call::IpDetection, 0.2), (Call::DNSProvider, 0.5)]);
+
+        let failed = Report::new(OutputFormat::Text, "update");
+        metrics.record_run::<()>(&failed, &Err(Error::IpNotFound), &[(Call::IpDetection, 3.0)]);
+
+        let out = metrics.render(4);
+
+        assert!(out.contains("gdu_ip_checks_total 2\n"));
+        assert!(out.contains("gdu_ip_changes_total 1\n"));
+        assert!(out.contains("gdu_provider_api_calls_total 4\n"));
+        assert!(out.contains("gdu_errors_total{kind=\"IpNotFound\"} 1\n"));
+        assert!(out.contains("gdu_published_ip{ip=\"100.3.5.5\"} 1\n"));
+        assert!(out.contains("gdu_last_sync_timestamp_seconds "));
+        assert!(out.contains("gdu_ip_detection_duration_seconds_count 2\n"));
+        assert!(out.contains("gdu_dns_provider_duration_seconds_count 1\n"));
+    }
+
+    struct FailingIpProvider;
+
+    impl GetMyIpAddr<IpAddr> for FailingIpProvider {
+        fn get_my_ip_addr(&self) -> Result<IpAddr> {
+            Err(Error::IpNotFound)
+        }
+    }
+
+    fn run(metrics: &mut Metrics, ip_addr: &str, dry_run: bool) {
+        let mut report = Report::new(OutputFormat::Text, "update");
+        report.set_provider("gandi", "example.com", dry_run);
+        report.set_ip_address(&ip_addr.parse().unwrap());
+        report.set_outcome("updated");
+        metrics.record_run(&report, &Ok(()), &[]);
+    }
+
+    #[test]
+    fn published_ip() {
+        let mut metrics = Metrics::new();
+        run(&mut metrics, "100.3.5.5", false);
+        metrics.last_sync = Some(1476748800);
+
+        let failed = Report::new(OutputFormat::Text, "update");
+        metrics.record_run::<()>(&failed, &Err(Error::IpNotFound), &[]);
+        run(&mut metrics, "100.3.5.7", true);
+
+        let out = metrics.render(0);
+        assert!(out.contains("gdu_published_ip{ip=\"100.3.5.5\"} 1\n"));
+        assert!(out.contains("gdu_last_sync_timestamp_seconds 1476748800\n"));
+
+        run(&mut metrics, "100.3.5.6", false);
+
+        let out = metrics.render(0);
+        assert!(out.contains("gdu_published_ip{ip=\"100.3.5.6\"} 1\n"));
+        assert!(!out.contains("100.3.5.5"));
+        assert!(!out.contains("gdu_last_sync_timestamp_seconds 1476748800\n"));
+    }
+
+    #[test]
+    fn timed_calls() {
+        take_calls();
+
+        let provider = TimedIpProvider(Box::new(FailingIpProvider));
+        assert!(provider.get_my_ip_addr().is_err());
+        assert!(provider.get_my_ip_addr().is_err());
+
+        let calls = take_calls();
+        assert_eq!(vec![Call::IpDetection, Call::IpDetection],
+                   calls.iter().map(|&(call, _)| call).collect::<Vec<Call>>());
+        assert!(take_calls().is_empty());
+    }
+
+    #[test]
+    fn status() {
+        let mut metrics = Metrics::new();
+        assert_eq!("No IP address published yet", metrics.status());
+
+        metrics.published_ip = Some("100.3.5.5".parse().unwrap());
+        metrics.last_sync = Some(1476748800);
+        assert_eq!("IP address 100.3.5.5, last sync 2016-10-18 00:00:00 UTC",
+                   metrics.status());
+    }
+
+    #[test]
+    fn endpoint() {
+        let metrics = Arc::new(Mutex::new(Metrics::new()));
+        let mut listening = serve("127.0.0.1:0".parse().unwrap(), metrics).unwrap();
+
+        let mut stream = TcpStream::connect(listening.socket).unwrap();
+        write!(stream,
+               "GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("gdu_ip_checks_total 0"));
+
+        listening.close().unwrap();
+    }
+}
@@ -1,25 +1,73 @@
 use error::Result;
 use error::Error;
+use http::{HttpClient, HttpSettings, IpFamily};
+use metrics::TimedIpProvider;
 use std::net::IpAddr;
-use hyper::Client;
-use hyper::header::Connection;
 use std::io;
-use std::io::prelude::*;
 use std::result::Result as StdResult;
 use std::str::FromStr;
 use regex::Regex;
+use registry::{Registration, Registry};
+use socket::Source;
+use std::fmt;
 
 // All HTTP IP providers URL
 static URL_SFR_LABOX_FIBRE: &'static str = "http://192.168.0.1/";
 static URL_OPENDNS: &'static str = "https://diagnostic.opendns.com/myip";
 static URL_IPIFY: &'static str = "https://api.ipify.org/";
-
-#[derive(Debug)]
-pub enum IpProvider {
-    Stdin,
-    SfrLaBoxFibre,
-    OpenDNS,
-    Ipify,
+/// Dual-stack, answers with the address of the connection whatever its family
+static URL_IPIFY64: &'static str = "https://api64.ipify.org/";
+
+/// Constructor of an IP address provider, reached with the HTTP settings and looking for an
+/// address of the family if set
+type IpProviderBuild = fn(&HttpSettings, Option<IpFamily>) -> Box<GetMyIpAddr<IpAddr>>;
+
+/// IP address providers available
+pub static IP_PROVIDERS: Registry<IpProviderBuild> = Registry {
+    kind: "IP provider",
+    providers: &[Registration {
+                     name: "ipify",
+                     description: "Ipify (IPv4, or the family of --ip-family)",
+                     settings: &[],
+                     defaults: &[],
+                     build: ipify,
+                 },
+                 Registration {
+                     name: "opendns",
+                     description: "OpenDNS (IPv6, or the family of --ip-family)",
+                     settings: &[],
+                     defaults: &[],
+                     build: opendns,
+                 },
+                 Registration {
+                     name: "-",
+                     description: "read IP address from stdin",
+                     settings: &[],
+                     defaults: &[],
+                     build: stdin,
+                 },
+                 Registration {
+                     name: "sfrlaboxfibre",
+                     description: "French 'SFR Labox Fibre' subscribers (IPv4)",
+                     settings: &[],
+                     defaults: &[],
+                     build: sfr_labox_fibre,
+                 }],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpProvider(&'static Registration<IpProviderBuild>);
+
+/// IP address provider, and how to reach it
+#[derive(Debug, Clone)]
+pub struct IpDetector {
+    pub provider: IpProvider,
+    pub http: HttpSettings,
+    /// Family of the IP address to detect: the IP address provider is only reached with this
+    /// family, and an address of the other family is rejected
+    pub family: Option<IpFamily>,
+    /// Source address or interface of the lookup, e.g. to get the address of one WAN link
+    pub source: Option<Source>,
 }
 
 pub trait GetMyIpAddr<T> {
@@ -27,19 +75,16 @@ pub trait GetMyIpAddr<T> {
 }
 
 impl IpProvider {
-    fn build(&self) -> Box<GetMyIpAddr<IpAddr>> {
-        match self {
-            &IpProvider::Stdin => Box::new(StdinIpProvider),
-            &IpProvider::SfrLaBoxFibre => {
-                Box::new(FromRegexIpProvider::new(HttpIpProvider::new(URL_SFR_LABOX_FIBRE)))
-            }
-            &IpProvider::OpenDNS => {
-                Box::new(FromRegexIpProvider::new(HttpIpProvider::new(URL_OPENDNS)))
-            }
-            &IpProvider::Ipify => {
-                Box::new(FromRegexIpProvider::new(HttpIpProvider::new(URL_IPIFY)))
-            }
-        }
+    fn build(&self,
+             http: &HttpSettings,
+             family: Option<IpFamily>,
+             source: &Option<Source>)
+             -> Box<GetMyIpAddr<IpAddr>> {
+        let mut http = http.clone();
+        http.family = family;
+        http.source = source.clone();
+
+        Box::new(TimedIpProvider((self.0.build)(&http, family)))
     }
 }
 
@@ -47,19 +92,54 @@ impl FromStr for IpProvider {
     type Err = String;
 
     fn from_str(s: &str) -> StdResult<IpProvider, String> {
-        match s {
-            "-" => Ok(IpProvider::Stdin),
-            "sfrlaboxfibre" => Ok(IpProvider::SfrLaBoxFibre),
-            "opendns" => Ok(IpProvider::OpenDNS),
-            "ipify" => Ok(IpProvider::Ipify),
-            value => Err(format!("Unknown value for IP provider: {}", value).to_owned()),
-        }
+        IP_PROVIDERS.find(s).map(IpProvider)
+    }
+}
+
+impl fmt::Display for IpProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0.name)
     }
 }
 
-impl GetMyIpAddr<IpAddr> for IpProvider {
+fn stdin(_: &HttpSettings, _: Option<IpFamily>) -> Box<GetMyIpAddr<IpAddr>> {
+    Box::new(StdinIpProvider)
+}
+
+fn sfr_labox_fibre(http: &HttpSettings, family: Option<IpFamily>) -> Box<GetMyIpAddr<IpAddr>> {
+    Box::new(FromRegexIpProvider::new(HttpIpProvider::new(URL_SFR_LABOX_FIBRE, http), family))
+}
+
+fn opendns(http: &HttpSettings, family: Option<IpFamily>) -> Box<GetMyIpAddr<IpAddr>> {
+    Box::new(FromRegexIpProvider::new(HttpIpProvider::new(URL_OPENDNS, http), family))
+}
+
+fn ipify(http: &HttpSettings, family: Option<IpFamily>) -> Box<GetMyIpAddr<IpAddr>> {
+    let url = match family {
+        // api.ipify.org is IPv4 only
+        Some(_) => URL_IPIFY64,
+        None => URL_IPIFY,
+    };
+
+    Box::new(FromRegexIpProvider::new(HttpIpProvider::new(url, http), family))
+}
+
+impl GetMyIpAddr<IpAddr> for IpDetector {
     fn get_my_ip_addr(&self) -> Result<IpAddr> {
-        self.build().get_my_ip_addr()
+        let provider = self.provider.build(&self.http, self.family, &self.source);
+        let ip_addr = try!(provider.get_my_ip_addr());
+        check_family(ip_addr, self.family)
+    }
+}
+
+fn check_family(ip_addr: IpAddr, family: Option<IpFamily>) -> Result<IpAddr> {
+    match family {
+        Some(family) if !family.matches(&ip_addr) => {
+            Err(Error::WrongIpFamily(format!("{} detected instead of an {} address",
+                                             ip_addr,
+                                             family)))
+        }
+        _ => Ok(ip_addr),
     }
 }
 
@@ -86,44 +166,47 @@ impl GetMyIpAddr<IpAddr> for StdinIpProvider {
 
 struct HttpIpProvider<'a> {
     url: &'a str,
+    http: HttpSettings,
 }
 
 impl<'a> HttpIpProvider<'a> {
-    fn new(url: &'a str) -> HttpIpProvider {
-        HttpIpProvider { url: url }
+    fn new(url: &'a str, http: &HttpSettings) -> HttpIpProvider<'a> {
+        HttpIpProvider {
+            url: url,
+            http: http.clone(),
+        }
     }
 }
 
 impl<'a> GetMyIpAddr<String> for HttpIpProvider<'a> {
     fn get_my_ip_addr(&self) -> Result<String> {
-        let client = Client::new();
+        let client = try!(HttpClient::new(&self.http));
+        let response = try!(try!(client.get(self.url)).success(self.url));
 
-        let mut res = try!(client.get(self.url)
-            .header(Connection::close())
-            .send());
+        trace!("HTTP Response: {}", response.body);
 
-        let mut body = String::new();
-        try!(res.read_to_string(&mut body));
-
-        trace!("HTTP Response: {}", body);
-
-        Ok(body)
+        Ok(response.body)
     }
 }
 
 pub struct FromRegexIpProvider<P: GetMyIpAddr<String>> {
     provider: P,
+    /// Family of the address looked for, IPv4 first then IPv6 if not set
+    family: Option<IpFamily>,
 }
 
 impl<'a, P: GetMyIpAddr<String>> FromRegexIpProvider<P> {
-    fn new(provider: P) -> FromRegexIpProvider<P> {
-        FromRegexIpProvider { provider: provider }
+    fn new(provider: P, family: Option<IpFamily>) -> FromRegexIpProvider<P> {
+        FromRegexIpProvider {
+            provider: provider,
+            family: family,
+        }
     }
 }
 
 impl<P: GetMyIpAddr<String>> GetMyIpAddr<IpAddr> for FromRegexIpProvider<P> {
     fn get_my_ip_addr(&self) -> Result<IpAddr> {
-        let body = self.provider.get_my_ip_addr().unwrap();
+        let body = try!(self.provider.get_my_ip_addr());
 
         let ipv4_regex = try!(Regex::new(r"((?:(?:0|1[\d]{0,2}|2(?:[0-4]\d?|5[0-5]?|[6-9])?|[3-9]\d?)\.){3}(?:0|1[\d]{0,2}|2(?:[0-4]\d?|5[0-5]?|[6-9])?|[3-9]\d?))"));
         let ipv6_regex = try!(Regex::new(r"((([0-9A-Fa-f]{1,4}:){7}[0-9A-Fa-f]{1,4})|(([0-9A-Fa-f]{1,4}:){6}:[0-9A-Fa-f]{1,4})|(([0-9A-Fa-f]{1,4}:){5}:([0-9A-Fa-f]{1,4}:)?[0-9A-Fa-f]{1,4})|(([0-9A-Fa-f]{1,4}:){4}:([0-9A-Fa-f]{1,4}:){0,2}[0-9A-Fa-f]{1,4})|(([0-9A-Fa-f]{1,4}:){3}:([0-9A-Fa-f]{1,4}:){0,3}[0-9A-Fa-f]{1,4})|(([0-9A-Fa-f]{1,4}:){2}:([0-9A-Fa-f]{1,4}:){0,4}[0-9A-Fa-f]{1,4})|(([0-9A-Fa-f]{1,4}:){6}((\d((25[0-5])|(1\d{2})|(2[0-4]\d)|(\d{1,2}))\d)\.){3}(\d((25[0-5])|(1\d{2})|(2[0-4]\d)|(\d{1,2}))\d))|(([0-9A-Fa-f]{1,4}:){0,5}:((\d((25[0-5])|(1\d{2})|(2[0-4]\d)|(\d{1,2}))\d)\.){3}(\d((25[0-5])|(1\d{2})|(2[0-4]\d)|(\d{1,2}))\d))|(::([0-9A-Fa-f]{1,4}:){0,5}((\d((25[0-5])|(1\d{2})|(2[0-4]\d)|(\d{1,2}))\d)\.){3}(\d((25[0-5])|(1\d{2})|(2[0-4]\d)|(\d{1,2}))\d))|([0-9A-Fa-f]{1,4}::([0-9A-Fa-f]{1,4}:){0,5}[0-9A-Fa-f]{1,4})|(::([0-9A-Fa-f]{1,4}:){0,6}[0-9A-Fa-f]{1,4})|(([0-9A-Fa-f]{1,4}:){1,7}:))"));
@@ -135,7 +218,13 @@ impl<P: GetMyIpAddr<String>> GetMyIpAddr<IpAddr> for FromRegexIpProvider<P> {
         let maybe_ipv6 = ipv6_regex.captures(&*body)
             .and_then(|caps| caps.at(1));
 
-        maybe_ipv4.or(maybe_ipv6)
+        let maybe_ip = match self.family {
+            Some(IpFamily::V4) => maybe_ipv4,
+            Some(IpFamily::V6) => maybe_ipv6,
+            None => maybe_ipv4.or(maybe_ipv6),
+        };
+
+        maybe_ip
             // Convert Option to Result
             .ok_or(Error::IpNotFound)
             .and_then(|val| IpAddr::from_str(val).map_err(|e| {
@@ -148,8 +237,10 @@ impl<P: GetMyIpAddr<String>> GetMyIpAddr<IpAddr> for FromRegexIpProvider<P> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::check_family;
     use error::Error;
     use error::Result;
+    use http::IpFamily;
     use std::net::IpAddr;
     use std::str::FromStr;
 
@@ -166,7 +257,7 @@ mod tests {
 
     #[test]
     fn ipv4_addr() {
-        let provider = FromRegexIpProvider::new(IPv4BodyHP);
+        let provider = FromRegexIpProvider::new(IPv4BodyHP, None);
         let maybeResult = provider.get_my_ip_addr();
         assert_eq!(IpAddr::from_str(IP_V4).unwrap(), maybeResult.unwrap());
     }
@@ -181,7 +272,7 @@ mod tests {
 
     #[test]
     fn ipv6_addr() {
-        let provider = FromRegexIpProvider::new(IPv6BodyHP);
+        let provider = FromRegexIpProvider::new(IPv6BodyHP, None);
         let maybeResult = provider.get_my_ip_addr();
         assert_eq!(IpAddr::from_str(IP_V6).unwrap(), maybeResult.unwrap());
     }
@@ -196,9 +287,41 @@ mod tests {
 
     #[test]
     fn not_an_ip_addr() {
-        let provider = FromRegexIpProvider::new(NotIPAddrBodyHP);
+        let provider = FromRegexIpProvider::new(NotIPAddrBodyHP, None);
         let maybeResult = provider.get_my_ip_addr();
         assert!(maybeResult.is_err());
     }
 
+    struct DualStackBodyHP;
+
+    impl GetMyIpAddr<String> for DualStackBodyHP {
+        fn get_my_ip_addr(&self) -> Result<String> {
+            Ok(format!("{} {}", IP_V4, IP_V6))
+        }
+    }
+
+    #[test]
+    fn ip_addr_of_family() {
+        let provider = FromRegexIpProvider::new(DualStackBodyHP, Some(IpFamily::V6));
+        assert_eq!(IpAddr::from_str(IP_V6).unwrap(), provider.get_my_ip_addr().unwrap());
+
+        let provider = FromRegexIpProvider::new(IPv4BodyHP, Some(IpFamily::V6));
+        assert!(provider.get_my_ip_addr().is_err());
+    }
+
+    #[test]
+    fn wrong_family() {
+        let ip_v4 = IpAddr::from_str(IP_V4).unwrap();
+
+        assert_eq!(ip_v4, check_family(ip_v4, None).unwrap());
+        assert_eq!(ip_v4, check_family(ip_v4, Some(IpFamily::V4)).unwrap());
+        assert!(check_family(ip_v4, Some(IpFamily::V6)).is_err());
+    }
+
+    #[test]
+    fn ip_provider_from_str() {
+        assert_eq!("ipify", "ipify".parse::<IpProvider>().unwrap().to_string());
+        assert_eq!("-", "-".parse::<IpProvider>().unwrap().to_string());
+        assert!("whatismyip".parse::<IpProvider>().is_err());
+    }
 }
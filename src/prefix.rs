@@ -0,0 +1,199 @@
+use dns::ZoneRecord;
+use error::Error;
+use error::Result;
+use gandi;
+use std::net::Ipv6Addr;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+
+// IPv6 prefix delegation: LAN hosts get their addresses from the prefix delegated by the ISP,
+// each one with its own interface identifier (suffix), so all their AAAA records change when
+// the prefix does.
+
+/// LAN host and the suffix of its address, e.g. "nas=::211:32ff:fe12:3456"
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostSuffix {
+    pub name: String,
+    pub suffix: Ipv6Addr,
+}
+
+impl FromStr for HostSuffix {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<HostSuffix, String> {
+        let mut parts = s.splitn(2, '=');
+
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(suffix)) if !name.is_empty() => {
+                match Ipv6Addr::from_str(suffix) {
+                    Ok(suffix) => {
+                        Ok(HostSuffix {
+                            name: name.to_string(),
+                            suffix: suffix,
+                        })
+                    }
+                    Err(_) => Err(format!("Invalid IPv6 suffix for host '{}': {}", name, suffix)),
+                }
+            }
+            _ => Err(format!("Invalid host, expected <name>=<IPv6 suffix>: {}", s)),
+        }
+    }
+}
+
+/// Address made of the first `prefix_length` bits of `prefix` and of the remaining bits of
+/// `suffix`. Fails if the suffix has bits set within the prefix.
+pub fn host_address(prefix: &Ipv6Addr,
+                    prefix_length: u8,
+                    suffix: &Ipv6Addr)
+                    -> Result<Ipv6Addr> {
+    if prefix_length > 128 {
+        return Err(Error::InvalidRecord(format!("Invalid IPv6 prefix length: {}",
+                                                prefix_length)));
+    }
+
+    let prefix_segments = prefix.segments();
+    let suffix_segments = suffix.segments();
+    let mut segments = [0u16; 8];
+
+    for index in 0..8 {
+        let mask = segment_mask(prefix_length, index);
+
+        if suffix_segments[index] & mask != 0 {
+            return Err(Error::InvalidRecord(format!("Suffix {} overlaps the /{} prefix",
+                                                    suffix,
+                                                    prefix_length)));
+        }
+
+        segments[index] = (prefix_segments[index] & mask) | suffix_segments[index];
+    }
+
+    Ok(Ipv6Addr::new(segments[0],
+                     segments[1],
+                     segments[2],
+                     segments[3],
+                     segments[4],
+                     segments[5],
+                     segments[6],
+                     segments[7]))
+}
+
+/// Bits of the segment `index` which belong to the prefix
+fn segment_mask(prefix_length: u8, index: usize) -> u16 {
+    let bits = (prefix_length as usize).saturating_sub(index * 16);
+
+    match bits {
+        0 => 0,
+        bits if bits >= 16 => 0xFFFF,
+        bits => !(0xFFFFu16 >> bits),
+    }
+}
+
+/// AAAA records of the zone declared for the hosts
+pub fn current_records(hosts: &[HostSuffix], records: &[ZoneRecord]) -> Vec<ZoneRecord> {
+    records.iter()
+        .filter(|record| {
+            record.type_.to_uppercase() == "AAAA" &&
+            hosts.iter().any(|host| host.name == record.name)
+        })
+        .cloned()
+        .collect()
+}
+
+/// AAAA records the hosts should have within `prefix`.
+///
+/// Without `ttl`, the TTL of a host already declared is kept, otherwise the DNS provider
+/// default is used.
+pub fn desired_records(hosts: &[HostSuffix],
+                       prefix: &Ipv6Addr,
+                       prefix_length: u8,
+                       ttl: Option<u32>,
+                       current: &[ZoneRecord])
+                       -> Result<Vec<ZoneRecord>> {
+    let mut records = Vec::new();
+
+    for host in hosts {
+        let address = try!(host_address(prefix, prefix_length, &host.suffix));
+
+        let ttl = ttl.or(current.iter()
+                .find(|record| record.name == host.name)
+                .map(|record| record.ttl))
+            .unwrap_or(gandi::DEFAULT_TTL);
+
+        records.push(ZoneRecord {
+            name: host.name.clone(),
+            type_: "AAAA".to_string(),
+            ttl: ttl,
+            value: address.to_string(),
+        });
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::ZoneRecord;
+    use std::net::Ipv6Addr;
+
+    fn address(value: &str) -> Ipv6Addr {
+        value.parse().unwrap()
+    }
+
+    fn aaaa(name: &str, ttl: u32, value: &str) -> ZoneRecord {
+        ZoneRecord {
+            name: name.to_string(),
+            type_: "AAAA".to_string(),
+            ttl: ttl,
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_host() {
+        assert_eq!(HostSuffix {
+                       name: "nas".to_string(),
+                       suffix: address("::211:32ff:fe12:3456"),
+                   },
+                   "nas=::211:32ff:fe12:3456".parse().unwrap());
+        assert!("nas".parse::<HostSuffix>().is_err());
+        assert!("=::1".parse::<HostSuffix>().is_err());
+        assert!("nas=192.168.0.10".parse::<HostSuffix>().is_err());
+    }
+
+    #[test]
+    fn addresses_within_prefix() {
+        let prefix = address("2a01:ca07:1234:5601::3117");
+
+        assert_eq!(address("2a01:ca07:1234:5600::211:32ff:fe12:3456"),
+                   host_address(&prefix, 56, &address("::211:32ff:fe12:3456")).unwrap());
+        assert_eq!(address("2a01:ca07:1234:5602::10"),
+                   host_address(&prefix, 56, &address("0:0:0:2::10")).unwrap());
+        assert_eq!(address("2a01:ca07:1230::1"),
+                   host_address(&prefix, 44, &address("::1")).unwrap());
+    }
+
+    #[test]
+    fn suffix_overlapping_prefix() {
+        let prefix = address("2a01:ca07:1234:5601::3117");
+
+        assert!(host_address(&prefix, 64, &address("0:0:0:2::10")).is_err());
+        assert!(host_address(&prefix, 129, &address("::1")).is_err());
+    }
+
+    #[test]
+    fn records_of_hosts() {
+        let hosts = vec!["nas=::10".parse().unwrap(), "printer=::20".parse().unwrap()];
+        let zone = vec![aaaa("nas", 300, "2a01:ca07:1234:5600::10"),
+                        aaaa("www", 300, "2a01:ca07:1234:5600::30")];
+
+        let current = current_records(&hosts, &zone);
+        assert_eq!(vec![aaaa("nas", 300, "2a01:ca07:1234:5600::10")], current);
+
+        let prefix = address("2a01:ca07:1234:5700::1");
+
+        assert_eq!(vec![aaaa("nas", 300, "2a01:ca07:1234:5700::10"),
+                        aaaa("printer", 10800, "2a01:ca07:1234:5700::20")],
+                   desired_records(&hosts, &prefix, 56, None, &current).unwrap());
+    }
+}
@@ -0,0 +1,295 @@
+use dns::ProviderType;
+use error::Error;
+use error::Result;
+use rustc_serialize::Decodable;
+use socket::Source;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::net::IpAddr;
+use toml;
+
+// Config file with credential profiles, e.g. one per Gandi organisation, and the records each
+// of them manages:
+//
+// [profiles.work]
+// provider = "gandi"
+// apikey_file = "/run/secrets/gandi-work"
+//
+// [[records]]
+// profile = "work"
+// domain = "example.com"
+// name = "vpn"
+// interface = "ppp0"
+
+/// Credentials of an account of a DNS provider
+#[derive(Debug, PartialEq)]
+pub struct Profile {
+    pub provider: ProviderType,
+    /// Settings of the provider, decoded by its constructor, e.g. the source of the API key
+    pub settings: toml::Table,
+}
+
+/// Record kept up to date with the current IP address, with the credentials of `profile`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManagedRecord {
+    pub profile: String,
+    pub domain: String,
+    pub name: String,
+    pub ttl: Option<u32>,
+    /// Source address or interface of the IP address detection, e.g. of one WAN link
+    pub source: Option<Source>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ConfigFile {
+    pub profiles: BTreeMap<String, Profile>,
+    pub records: Vec<ManagedRecord>,
+}
+
+#[derive(RustcDecodable)]
+struct RawConfigFile {
+    records: Option<Vec<RawRecord>>,
+}
+
+#[derive(RustcDecodable)]
+struct RawRecord {
+    profile: String,
+    domain: String,
+    name: String,
+    ttl: Option<u32>,
+    interface: Option<String>,
+    source_address: Option<String>,
+}
+
+impl ConfigFile {
+    pub fn read(path: &str) -> Result<ConfigFile> {
+        let mut content = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut content));
+
+        ConfigFile::parse(&content).map_err(|err| match err {
+            Error::Config(label) => Error::Config(format!("{}: {}", path, label)),
+            err => err,
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<ConfigFile> {
+        let mut parser = toml::Parser::new(content);
+
+        let mut table = match parser.parse() {
+            Some(table) => table,
+            None => {
+                let errors = parser.errors
+                    .iter()
+                    .map(|err| {
+                        let (line, _) = parser.to_linecol(err.lo);
+                        format!("line {}: {}", line + 1, err.desc)
+                    })
+                    .collect::<Vec<String>>();
+                return Err(Error::Config(errors.join(", ")));
+            }
+        };
+
+        let mut profiles = BTreeMap::new();
+        match table.remove("profiles") {
+            Some(toml::Value::Table(raw_profiles)) => {
+                for (name, raw_profile) in raw_profiles {
+                    let profile = try!(profile(&name, raw_profile));
+                    profiles.insert(name, profile);
+                }
+            }
+            Some(_) => return Err(Error::Config("profiles must be a table".to_string())),
+            None => {}
+        }
+
+        let mut decoder = toml::Decoder::new(toml::Value::Table(table));
+        let raw = try!(RawConfigFile::decode(&mut decoder)
+            .map_err(|err| Error::Config(err.to_string())));
+
+        let mut records = Vec::new();
+        for raw_record in raw.records.unwrap_or_default() {
+            records.push(try!(record(raw_record)));
+        }
+
+        for record in &records {
+            if !profiles.contains_key(&record.profile) {
+                return Err(Error::Config(format!("Unknown profile '{}' for record '{}' of '{}'",
+                                                 record.profile,
+                                                 record.name,
+                                                 record.domain)));
+            }
+        }
+
+        Ok(ConfigFile {
+            profiles: profiles,
+            records: records,
+        })
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles
+            .get(name)
+            .ok_or(Error::Config(format!("Unknown profile '{}'", name)))
+    }
+}
+
+/// Checks that the detection leaves from an interface or a valid source address, not both
+fn record(raw: RawRecord) -> Result<ManagedRecord> {
+    let source = match (raw.interface, raw.source_address) {
+        (None, None) => None,
+        (Some(interface), None) => Some(Source::Interface(interface)),
+        (None, Some(address)) => {
+            match address.parse::<IpAddr>() {
+                Ok(address) => Some(Source::Address(address)),
+                Err(_) => {
+                    return Err(Error::Config(format!("Record '{}' of '{}': invalid \
+                                                      source_address {}",
+                                                     raw.name,
+                                                     raw.domain,
+                                                     address)))
+                }
+            }
+        }
+        (Some(_), Some(_)) => {
+            return Err(Error::Config(format!("Record '{}' of '{}': interface and \
+                                              source_address can't be both set",
+                                             raw.name,
+                                             raw.domain)))
+        }
+    };
+
+    Ok(ManagedRecord {
+        profile: raw.profile,
+        domain: raw.domain,
+        name: raw.name,
+        ttl: raw.ttl,
+        source: source,
+    })
+}
+
+/// Checks the provider and that its settings are known, leaving their decoding to the provider
+fn profile(name: &str, raw: toml::Value) -> Result<Profile> {
+    let mut settings = match raw {
+        toml::Value::Table(settings) => settings,
+        _ => return Err(Error::Config(format!("Profile '{}' must be a table", name))),
+    };
+
+    let provider = match settings.remove("provider") {
+        Some(toml::Value::String(provider)) => {
+            try!(provider.parse::<ProviderType>()
+                .map_err(|err| Error::Config(format!("Profile '{}': {}", name, err))))
+        }
+        Some(_) => {
+            return Err(Error::Config(format!("Profile '{}': provider must be a string", name)))
+        }
+        None => return Err(Error::Config(format!("Profile '{}': provider is required", name))),
+    };
+
+    for setting in settings.keys() {
+        if !provider.has_setting(setting) {
+            return Err(Error::Config(format!("Profile '{}': unknown setting '{}' of DNS \
+                                              provider {}",
+                                             name,
+                                             setting,
+                                             provider)));
+        }
+    }
+
+    Ok(Profile {
+        provider: provider,
+        settings: settings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::ProviderType;
+    use socket::Source;
+    use toml;
+
+    #[test]
+    fn profiles_and_records() {
+        let config = ConfigFile::parse(r#"
+[profiles.work]
+provider = "gandi"
+apikey_file = "/run/secrets/gandi-work"
+
+[profiles.home]
+provider = "gandi"
+apikey_command = "pass show gandi"
+
+[[records]]
+profile = "work"
+domain = "example.com"
+name = "vpn"
+
+[[records]]
+profile = "home"
+domain = "example.org"
+name = "nas"
+ttl = 300
+interface = "ppp0"
+"#)
+            .unwrap();
+
+        assert_eq!(2, config.profiles.len());
+        assert_eq!("gandi".parse::<ProviderType>().unwrap(),
+                   config.profile("work").unwrap().provider);
+        assert_eq!(Some(&toml::Value::String("pass show gandi".to_string())),
+                   config.profile("home").unwrap().settings.get("apikey_command"));
+        assert_eq!(1, config.profile("home").unwrap().settings.len());
+        assert_eq!(ManagedRecord {
+                       profile: "home".to_string(),
+                       domain: "example.org".to_string(),
+                       name: "nas".to_string(),
+                       ttl: Some(300),
+                       source: Some(Source::Interface("ppp0".to_string())),
+                   },
+                   config.records[1]);
+        assert_eq!(None, config.records[0].source);
+        assert!(config.profile("other").is_err());
+    }
+
+    #[test]
+    fn invalid_profiles() {
+        // Unknown provider
+        assert!(ConfigFile::parse("[profiles.work]\nprovider = \"route53\"\napikey = \"key\"")
+            .is_err());
+        // No provider
+        assert!(ConfigFile::parse("[profiles.work]\napikey = \"key\"").is_err());
+        // Setting of another provider
+        assert!(ConfigFile::parse("[profiles.work]\nprovider = \"gandi\"\napikey = \"key\"\n\
+                                   secret_key = \"secret\"")
+            .is_err());
+    }
+
+    #[test]
+    fn detection_sources() {
+        let record = "[profiles.work]\nprovider = \"gandi\"\napikey = \"key\"\n[[records]]\n\
+                      profile = \"work\"\ndomain = \"example.com\"\nname = \"vpn\"\n";
+
+        assert_eq!(Some(Source::Address("192.168.1.2".parse().unwrap())),
+                   ConfigFile::parse(&format!("{}source_address = \"192.168.1.2\"", record))
+                       .unwrap()
+                       .records[0]
+                       .source);
+        assert!(ConfigFile::parse(&format!("{}source_address = \"ppp0\"", record)).is_err());
+        assert!(ConfigFile::parse(&format!("{}interface = \"ppp0\"\nsource_address = \
+                                            \"192.168.1.2\"",
+                                           record))
+            .is_err());
+    }
+
+    #[test]
+    fn unknown_profile() {
+        assert!(ConfigFile::parse("[[records]]\nprofile = \"work\"\ndomain = \"example.com\"\n\
+                                   name = \"vpn\"")
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_toml() {
+        assert!(ConfigFile::parse("[profiles.work\nprovider = \"gandi\"").is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use error::Error;
+use error::Result;
+use resolver::{Answer, QueryType, Resolver};
+use std::fmt;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DNS_PORT: u16 = 53;
+
+/// Delay between two queries to the authoritative nameservers
+const POLL_INTERVAL_SECS: u64 = 5;
+
+/// Authoritative nameserver of a zone
+#[derive(Debug, Clone)]
+pub struct Nameserver {
+    pub name: String,
+    pub addr: SocketAddr,
+}
+
+/// What a nameserver serves for the checked record, after the last query
+#[derive(Debug)]
+pub struct NameserverStatus {
+    pub nameserver: Nameserver,
+    pub visible: bool,
+    pub error: Option<String>,
+}
+
+impl fmt::Display for NameserverStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} ({}): ", self.nameserver.name, self.nameserver.addr));
+        match (self.visible, &self.error) {
+            (true, _) => write!(f, "up to date"),
+            (false, &Some(ref error)) => write!(f, "not up to date, {}", error),
+            (false, &None) => write!(f, "not up to date"),
+        }
+    }
+}
+
+/// Resolver used to look up the nameservers: the given one, or the system one
+pub fn resolver(address: Option<SocketAddr>) -> Result<Resolver> {
+    match address {
+        Some(address) => Ok(Resolver::new(address)),
+        None => Resolver::system(),
+    }
+}
+
+/// Fully qualified name of the record `name` of `zone`, "@" being the zone apex
+pub fn fqdn(name: &str, zone: &str) -> String {
+    let zone = zone.trim_right_matches('.');
+
+    match name {
+        "@" => zone.to_string(),
+        _ => format!("{}.{}", name, zone),
+    }
+}
+
+/// Looks up the authoritative nameservers of `zone` and waits until all of them serve the
+/// expected answers for the record `name` of the zone
+pub fn check<F>(resolver: &Resolver,
+                zone: &str,
+                name: &str,
+                query_type: QueryType,
+                is_expected: F,
+                timeout: Duration)
+                -> Result<Vec<NameserverStatus>>
+    where F: Fn(&[Answer]) -> bool
+{
+    let nameservers = try!(authoritative_nameservers(resolver, zone));
+
+    Ok(wait_until_served(&nameservers,
+                         &fqdn(name, zone),
+                         query_type,
+                         is_expected,
+                         timeout,
+                         Duration::from_secs(POLL_INTERVAL_SECS)))
+}
+
+/// Looks up the NS set of `zone` with `resolver`, and the address of each nameserver
+pub fn authoritative_nameservers(resolver: &Resolver, zone: &str) -> Result<Vec<Nameserver>> {
+    let mut nameservers = Vec::new();
+
+    for answer in try!(resolver.query(zone, QueryType::NS)) {
+        if let Answer::NS(name) = answer {
+            match try!(resolver.lookup_host(&name)).into_iter().next() {
+                Some(ip_addr) => {
+                    nameservers.push(Nameserver {
+                        name: name,
+                        addr: SocketAddr::new(ip_addr, DNS_PORT),
+                    })
+                }
+                None => warn!("No address found for nameserver '{}', ignored", name),
+            }
+        }
+    }
+
+    if nameservers.is_empty() {
+        return Err(Error::Dns(format!("No authoritative nameserver found for '{}'", zone)));
+    }
+
+    debug!("Authoritative nameservers of '{}': {:?}", zone, nameservers);
+    Ok(nameservers)
+}
+
+/// Queries each nameserver directly until all of them serve the expected answers for `name`,
+/// or until `timeout`. Returns the status of each nameserver after its last query.
+pub fn wait_until_served<F>(nameservers: &[Nameserver],
+                            name: &str,
+                            query_type: QueryType,
+                            is_expected: F,
+                            timeout: Duration,
+                            interval: Duration)
+                            -> Vec<NameserverStatus>
+    where F: Fn(&[Answer]) -> bool
+{
+    let start = Instant::now();
+    let mut statuses = nameservers.iter()
+        .map(|nameserver| {
+            NameserverStatus {
+                nameserver: nameserver.clone(),
+                visible: false,
+                error: None,
+            }
+        })
+        .collect::<Vec<NameserverStatus>>();
+
+    loop {
+        for status in statuses.iter_mut().filter(|status| !status.visible) {
+            let mut resolver = Resolver::authoritative(status.nameserver.addr);
+            resolver.set_timeout(interval);
+
+            match resolver.query(name, query_type) {
+                Ok(answers) => {
+                    status.visible = is_expected(&answers);
+                    status.error = None;
+                }
+                Err(err) => status.error = Some(err.to_string()),
+            }
+
+            debug!("{} {:?} on {}", name, query_type, status);
+        }
+
+        if statuses.iter().all(|status| status.visible) || start.elapsed() >= timeout {
+            return statuses;
+        }
+
+        info!("Waiting for '{}' to be served by {} of {} nameservers",
+              name,
+              statuses.iter().filter(|status| !status.visible).count(),
+              statuses.len());
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resolver::{Answer, QueryType, Resolver};
+    use resolver::testing::{LocalServer, a, ns, txt};
+    use std::net::IpAddr;
+    use std::thread;
+    use std::time::Duration;
+
+    fn nameserver(server: &LocalServer) -> Nameserver {
+        Nameserver {
+            name: "ns.example.net".to_string(),
+            addr: server.addr,
+        }
+    }
+
+    fn has_txt(answers: &[Answer]) -> bool {
+        answers.contains(&Answer::TXT(vec!["digest".to_string()]))
+    }
+
+    #[test]
+    fn record_fqdn() {
+        assert_eq!("example.com", fqdn("@", "example.com."));
+        assert_eq!("home.example.com", fqdn("home", "example.com"));
+    }
+
+    #[test]
+    fn find_nameservers() {
+        let server = LocalServer::start(vec![ns("example.com", "ns1.example.net"),
+                                             a("ns1.example.net", [127, 0, 0, 53])]);
+
+        let nameservers = authoritative_nameservers(&Resolver::new(server.addr), "example.com")
+            .unwrap();
+
+        assert_eq!(1, nameservers.len());
+        assert_eq!("ns1.example.net", nameservers[0].name);
+        assert_eq!("127.0.0.53:53", nameservers[0].addr.to_string());
+    }
+
+    #[test]
+    fn no_nameserver() {
+        let server = LocalServer::start(vec![]);
+        assert!(authoritative_nameservers(&Resolver::new(server.addr), "example.com").is_err());
+    }
+
+    #[test]
+    fn served_after_a_while() {
+        let up_to_date = LocalServer::start(vec![txt("_acme-challenge.example.com", "digest")]);
+        let late = LocalServer::start(vec![]);
+        let nameservers = vec![nameserver(&up_to_date), nameserver(&late)];
+
+        let records = late.records.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(150));
+            *records.lock().unwrap() = vec![txt("_acme-challenge.example.com", "digest")];
+        });
+
+        let statuses = wait_until_served(&nameservers,
+                                         "_acme-challenge.example.com",
+                                         QueryType::TXT,
+                                         has_txt,
+                                         Duration::from_secs(5),
+                                         Duration::from_millis(50));
+
+        assert!(statuses.iter().all(|status| status.visible));
+    }
+
+    #[test]
+    fn not_served_before_timeout() {
+        let outdated = LocalServer::start(vec![txt("_acme-challenge.example.com", "old")]);
+
+        let statuses = wait_until_served(&[nameserver(&outdated)],
+                                         "_acme-challenge.example.com",
+                                         QueryType::TXT,
+                                         has_txt,
+                                         Duration::from_millis(200),
+                                         Duration::from_millis(50));
+
+        assert!(!statuses[0].visible);
+        assert_eq!(format!("ns.example.net ({}): not up to date", outdated.addr),
+                   statuses[0].to_string());
+    }
+
+    #[test]
+    fn outdated_address() {
+        let up_to_date = LocalServer::start(vec![a("home.example.com", [100, 3, 5, 5])]);
+        let outdated = LocalServer::start(vec![a("home.example.com", [100, 3, 5, 4])]);
+        let expected = Answer::from("100.3.5.5".parse::<IpAddr>().unwrap());
+
+        let statuses = wait_until_served(&[nameserver(&up_to_date), nameserver(&outdated)],
+                                         "home.example.com",
+                                         QueryType::A,
+                                         |answers| answers == &[expected.clone()],
+                                         Duration::from_millis(100),
+                                         Duration::from_millis(50));
+
+        assert!(statuses[0].visible);
+        assert!(!statuses[1].visible);
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+use std::result::Result as StdResult;
+
+// Registries of the providers compiled in, e.g. DNS or IP address providers. Each provider
+// declares its name, description, settings in the config file and constructor, from which the
+// CLI help, the parsing of provider names, the validation of the config file and the
+// `providers` command are generated: adding a provider is adding an entry to its registry.
+
+/// A provider, built with a constructor of type `B`
+pub struct Registration<B> {
+    /// Name given on the command line or in the config file, e.g. "gandi"
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Settings of the provider in a profile of the config file, with their description
+    pub settings: &'static [(&'static str, &'static str)],
+    /// Settings used when none is given on the command line, with their value
+    pub defaults: &'static [(&'static str, &'static str)],
+    pub build: B,
+}
+
+impl<B> fmt::Debug for Registration<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl<B> PartialEq for Registration<B> {
+    fn eq(&self, other: &Registration<B>) -> bool {
+        self.name == other.name
+    }
+}
+
+impl<B> Registration<B> {
+    /// Whether `name` is a setting of the provider
+    pub fn has_setting(&self, name: &str) -> bool {
+        self.settings.iter().any(|&(setting, _)| setting == name)
+    }
+}
+
+/// All the providers of a kind
+pub struct Registry<B: 'static> {
+    /// Kind of the providers, e.g. "DNS provider"
+    pub kind: &'static str,
+    pub providers: &'static [Registration<B>],
+}
+
+impl<B: 'static> Registry<B> {
+    pub fn find(&self, name: &str) -> StdResult<&'static Registration<B>, String> {
+        self.providers
+            .iter()
+            .find(|provider| provider.name == name)
+            .ok_or_else(|| {
+                format!("Unknown {}: {} (available values: {})",
+                        self.kind,
+                        name,
+                        self.names().join(", "))
+            })
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.providers.iter().map(|provider| provider.name).collect()
+    }
+
+    /// Names and descriptions of the providers, one per line, each line but the first one
+    /// starting with `indent`, e.g. to align them in the CLI help
+    pub fn help(&self, indent: &str) -> String {
+        let width = self.providers.iter().map(|provider| provider.name.len()).max().unwrap_or(0);
+
+        self.providers
+            .iter()
+            .map(|provider| {
+                format!("{:width$} : {}",
+                        provider.name,
+                        provider.description,
+                        width = width)
+            })
+            .collect::<Vec<String>>()
+            .join(&format!("\n{}", indent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static REGISTRY: Registry<fn() -> u8> = Registry {
+        kind: "test provider",
+        providers: &[Registration {
+                         name: "one",
+                         description: "First provider",
+                         settings: &[("apikey", "API key")],
+                         defaults: &[],
+                         build: one,
+                     },
+                     Registration {
+                         name: "three",
+                         description: "Third provider",
+                         settings: &[],
+                         defaults: &[],
+                         build: three,
+                     }],
+    };
+
+    fn one() -> u8 {
+        1
+    }
+
+    fn three() -> u8 {
+        3
+    }
+
+    #[test]
+    fn find() {
+        assert_eq!(3, (REGISTRY.find("three").unwrap().build)());
+        assert!(REGISTRY.find("one").unwrap().has_setting("apikey"));
+        assert!(!REGISTRY.find("three").unwrap().has_setting("apikey"));
+        assert_eq!(Err("Unknown test provider: two (available values: one, three)".to_string()),
+                   REGISTRY.find("two").map(|provider| provider.name));
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!("one   : First provider\n  three : Third provider",
+                   REGISTRY.help("  "));
+    }
+}
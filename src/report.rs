@@ -0,0 +1,373 @@
+use dns::Change;
+use dns::ZoneRecord;
+use propagation::NameserverStatus;
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+use std::time::Instant;
+
+// Result document of a run, printed on stdout with `--output json` for fleet tooling, logs
+// being kept on stderr
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<OutputFormat, String> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            value => Err(format!("Unknown value for output format: {}", value)),
+        }
+    }
+}
+
+/// A record handled by the run, and what has been done with it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordReport {
+    pub name: String,
+    pub type_: String,
+    /// "created", "updated", "deleted" or "unchanged", none if only read
+    pub action: Option<String>,
+    pub previous_value: Option<String>,
+    pub value: Option<String>,
+    pub ttl: Option<u32>,
+}
+
+impl RecordReport {
+    /// Report of a change, `current` records of the zone give its previous value
+    pub fn from_change(change: &Change, current: &[ZoneRecord]) -> RecordReport {
+        let (action, record, value) = match change {
+            &Change::Create(ref record, ref value) => ("created", record, Some(value)),
+            &Change::Update(ref record, ref value) => ("updated", record, Some(value)),
+            &Change::Delete(ref record) => ("deleted", record, None),
+        };
+
+        let type_ = record.type_.to_string();
+        let previous_values = current.iter()
+            .filter(|current| current.name == record.name && current.type_.to_uppercase() == type_)
+            .map(|current| current.value.clone())
+            .collect::<Vec<String>>();
+
+        RecordReport {
+            name: record.name.to_string(),
+            type_: type_,
+            action: Some(action.to_string()),
+            previous_value: match action {
+                "created" => None,
+                _ if previous_values.is_empty() => None,
+                _ => Some(previous_values.join(" ")),
+            },
+            value: value.map(|value| value.to_string()),
+            ttl: record.ttl,
+        }
+    }
+
+    /// Report of a record of the zone, only read
+    pub fn from_zone_record(record: &ZoneRecord) -> RecordReport {
+        RecordReport {
+            name: record.name.clone(),
+            type_: record.type_.clone(),
+            action: None,
+            previous_value: None,
+            value: Some(record.value.clone()),
+            ttl: Some(record.ttl),
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("name".to_string(), Json::String(self.name.clone()));
+        object.insert("type".to_string(), Json::String(self.type_.clone()));
+        insert_string(&mut object, "action", &self.action);
+        insert_string(&mut object, "previous_value", &self.previous_value);
+        insert_string(&mut object, "value", &self.value);
+        if let Some(ttl) = self.ttl {
+            object.insert("ttl".to_string(), Json::U64(ttl as u64));
+        }
+        Json::Object(object)
+    }
+}
+
+pub struct Report {
+    format: OutputFormat,
+    start: Instant,
+    command: String,
+    provider: Option<String>,
+    domain: Option<String>,
+    dry_run: Option<bool>,
+    ip_address: Option<IpAddr>,
+    outcome: Option<String>,
+    records: Vec<RecordReport>,
+    zone_version: Option<u16>,
+    nameservers: Vec<Json>,
+    hooks: BTreeMap<String, Json>,
+    timings: BTreeMap<String, Json>,
+    /// Documents of the records updated one after the other, e.g. of the config file
+    runs: Vec<Json>,
+}
+
+impl Report {
+    pub fn new(format: OutputFormat, command: &str) -> Report {
+        Report {
+            format: format,
+            start: Instant::now(),
+            command: command.to_string(),
+            provider: None,
+            domain: None,
+            dry_run: None,
+            ip_address: None,
+            outcome: None,
+            records: Vec::new(),
+            zone_version: None,
+            nameservers: Vec::new(),
+            hooks: BTreeMap::new(),
+            timings: BTreeMap::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    /// Prints `line` on stdout with the text output, the JSON document being the only output
+    /// otherwise
+    pub fn println(&self, line: &str) {
+        if self.format == OutputFormat::Text {
+            println!("{}", line);
+        }
+    }
+
+    pub fn set_provider(&mut self, provider: &str, domain: &str, dry_run: bool) {
+        self.provider = Some(provider.to_string());
+        self.domain = Some(domain.to_string());
+        self.dry_run = Some(dry_run);
+    }
+
+    pub fn set_ip_address(&mut self, ip_address: &IpAddr) {
+        self.ip_address = Some(*ip_address);
+    }
+
+    pub fn ip_address(&self) -> Option<IpAddr> {
+        self.ip_address
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.unwrap_or(false)
+    }
+
+    /// Overall outcome of the command, e.g. "updated" or "outdated"
+    pub fn set_outcome(&mut self, outcome: &str) {
+        self.outcome = Some(outcome.to_string());
+    }
+
+    pub fn outcome(&self) -> Option<&str> {
+        self.outcome.as_ref().map(|outcome| &outcome[..])
+    }
+
+    pub fn add_record(&mut self, record: RecordReport) {
+        self.records.push(record);
+    }
+
+    pub fn set_zone_version(&mut self, zone_version: Option<u16>) {
+        self.zone_version = zone_version;
+    }
+
+    pub fn add_nameserver(&mut self, status: &NameserverStatus) {
+        let mut object = BTreeMap::new();
+        object.insert("name".to_string(), Json::String(status.nameserver.name.clone()));
+        object.insert("address".to_string(),
+                      Json::String(status.nameserver.addr.to_string()));
+        object.insert("served".to_string(), Json::Boolean(status.visible));
+        insert_string(&mut object, "error", &status.error);
+        self.nameservers.push(Json::Object(object));
+    }
+
+    /// Result of a hook run on IP address change, `error` being none on success
+    pub fn add_hook(&mut self, hook: &str, error: Option<String>) {
+        let mut object = BTreeMap::new();
+        object.insert("success".to_string(), Json::Boolean(error.is_none()));
+        insert_string(&mut object, "error", &error);
+        self.hooks.insert(hook.to_string(), Json::Object(object));
+    }
+
+    /// Records the time spent in `phase` since `start`
+    pub fn timing(&mut self, phase: &str, start: Instant) {
+        self.timings.insert(phase.to_string(), Json::U64(millis(start)));
+    }
+
+    /// Adds the report of a record updated as part of the command, with its exit code and error.
+    ///
+    /// The IP address and the dry run mode of the record become the command ones.
+    pub fn add_run(&mut self, mut run: Report, exit_code: i32, error: Option<(&str, String)>) {
+        if run.ip_address.is_some() {
+            self.ip_address = run.ip_address;
+        }
+        if run.dry_run.is_some() {
+            self.dry_run = run.dry_run;
+        }
+
+        let start = run.start;
+        run.timing("total", start);
+        self.runs.push(run.to_json(exit_code, error));
+    }
+
+    /// Prints the JSON document, if required
+    pub fn finish(mut self, exit_code: i32, error: Option<(&str, String)>) {
+        if self.format != OutputFormat::Json {
+            return;
+        }
+
+        let start = self.start;
+        self.timing("total", start);
+
+        println!("{}", self.to_json(exit_code, error).pretty());
+    }
+
+    fn to_json(&self, exit_code: i32, error: Option<(&str, String)>) -> Json {
+        let mut document = BTreeMap::new();
+        document.insert("command".to_string(), Json::String(self.command.clone()));
+        insert_string(&mut document, "provider", &self.provider);
+        insert_string(&mut document, "domain", &self.domain);
+        if let Some(dry_run) = self.dry_run {
+            document.insert("dry_run".to_string(), Json::Boolean(dry_run));
+        }
+        insert_string(&mut document,
+                      "ip_address",
+                      &self.ip_address.map(|ip_address| ip_address.to_string()));
+        insert_string(&mut document, "outcome", &self.outcome);
+        document.insert("records".to_string(),
+                        Json::Array(self.records.iter().map(|record| record.to_json()).collect()));
+        if let Some(zone_version) = self.zone_version {
+            document.insert("zone_version".to_string(), Json::U64(zone_version as u64));
+        }
+        if !self.nameservers.is_empty() {
+            document.insert("nameservers".to_string(), Json::Array(self.nameservers.clone()));
+        }
+        if !self.hooks.is_empty() {
+            document.insert("hooks".to_string(), Json::Object(self.hooks.clone()));
+        }
+        if !self.runs.is_empty() {
+            document.insert("runs".to_string(), Json::Array(self.runs.clone()));
+        }
+        document.insert("timings_ms".to_string(), Json::Object(self.timings.clone()));
+        document.insert("exit_code".to_string(), Json::I64(exit_code as i64));
+        if let Some((kind, message)) = error {
+            let mut object = BTreeMap::new();
+            object.insert("kind".to_string(), Json::String(kind.to_string()));
+            object.insert("message".to_string(), Json::String(message));
+            document.insert("error".to_string(), Json::Object(object));
+        }
+
+        Json::Object(document)
+    }
+}
+
+fn insert_string(object: &mut BTreeMap<String, Json>, key: &str, value: &Option<String>) {
+    if let &Some(ref value) = value {
+        object.insert(key.to_string(), Json::String(value.clone()));
+    }
+}
+
+fn millis(start: Instant) -> u64 {
+    let elapsed = start.elapsed();
+    elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1000_000) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::{Change, Record, RecordType, RecordValue, ZoneRecord};
+    use rustc_serialize::json::Json;
+
+    fn current() -> Vec<ZoneRecord> {
+        vec![ZoneRecord {
+                 name: "home".to_string(),
+                 type_: "A".to_string(),
+                 ttl: 300,
+                 value: "100.3.5.4".to_string(),
+             }]
+    }
+
+    #[test]
+    fn updated_record() {
+        let mut record = Record::of_type("home", RecordType::A);
+        record.ttl = Some(300);
+        let change = Change::Update(record,
+                                    RecordValue::parse(&RecordType::A, "100.3.5.5").unwrap());
+
+        assert_eq!(RecordReport {
+                       name: "home".to_string(),
+                       type_: "A".to_string(),
+                       action: Some("updated".to_string()),
+                       previous_value: Some("100.3.5.4".to_string()),
+                       value: Some("100.3.5.5".to_string()),
+                       ttl: Some(300),
+                   },
+                   RecordReport::from_change(&change, &current()));
+    }
+
+    #[test]
+    fn deleted_record() {
+        let change = Change::Delete(Record::of_type("home", RecordType::A));
+        let report = RecordReport::from_change(&change, &current());
+
+        assert_eq!(Some("deleted".to_string()), report.action);
+        assert_eq!(Some("100.3.5.4".to_string()), report.previous_value);
+        assert_eq!(None, report.value);
+    }
+
+    #[test]
+    fn json_document() {
+        let mut report = Report::new(OutputFormat::Json, "update");
+        report.set_provider("gandi", "example.com", false);
+        report.set_ip_address(&"100.3.5.5".parse().unwrap());
+        report.set_outcome("created");
+        report.set_zone_version(Some(12));
+
+        let json = report.to_json(21, Some(("auth", "Invalid API key".to_string())));
+
+        assert_eq!(Some(&Json::String("update".to_string())), json.find("command"));
+        assert_eq!(Some(&Json::String("100.3.5.5".to_string())), json.find("ip_address"));
+        assert_eq!(Some(&Json::U64(12)), json.find("zone_version"));
+        assert_eq!(Some(&Json::I64(21)), json.find("exit_code"));
+        assert_eq!(Some(&Json::String("auth".to_string())),
+                   json.find_path(&["error", "kind"]));
+        assert_eq!(Some(&Json::Array(vec![])), json.find("records"));
+        assert_eq!(None, json.find("runs"));
+    }
+
+    #[test]
+    fn runs() {
+        let mut report = Report::new(OutputFormat::Json, "update");
+
+        let mut run = Report::new(OutputFormat::Json, "update");
+        run.set_provider("gandi", "example.com", false);
+        run.set_ip_address(&"100.3.5.5".parse().unwrap());
+        report.add_run(run, 0, None);
+
+        let mut failed = Report::new(OutputFormat::Json, "update");
+        failed.set_provider("gandi", "example.org", false);
+        report.add_run(failed, 21, Some(("auth", "Invalid API key".to_string())));
+
+        let json = report.to_json(21, None);
+        let runs = json.find("runs").and_then(|runs| runs.as_array()).unwrap();
+
+        assert_eq!(Some("100.3.5.5".to_string()), report.ip_address.map(|ip| ip.to_string()));
+        assert_eq!(2, runs.len());
+        assert_eq!(Some(&Json::String("example.com".to_string())), runs[0].find("domain"));
+        assert_eq!(Some(&Json::String("example.org".to_string())), runs[1].find("domain"));
+        assert_eq!(Some(&Json::I64(21)), runs[1].find("exit_code"));
+        assert_eq!(Some(&Json::String("auth".to_string())),
+                   runs[1].find_path(&["error", "kind"]));
+        assert!(runs[0].find_path(&["timings_ms", "total"]).is_some());
+    }
+}
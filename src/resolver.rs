@@ -0,0 +1,586 @@
+use error::Error;
+use error::Result;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// Minimal DNS client over UDP (RFC 1035), enough to find the nameservers of a zone and to ask
+// them directly for A, AAAA and TXT records. Truncated responses are queried again over TCP.
+
+static RESOLV_CONF: &'static str = "/etc/resolv.conf";
+
+const DNS_PORT: u16 = 53;
+const ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryType {
+    A,
+    NS,
+    TXT,
+    AAAA,
+}
+
+impl QueryType {
+    fn code(&self) -> u16 {
+        match self {
+            &QueryType::A => 1,
+            &QueryType::NS => 2,
+            &QueryType::TXT => 16,
+            &QueryType::AAAA => 28,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Answer {
+    A(Ipv4Addr),
+    NS(String),
+    TXT(Vec<String>),
+    AAAA(Ipv6Addr),
+    /// Any other record type, e.g. a CNAME followed by a recursive resolver
+    Other(u16),
+}
+
+impl From<IpAddr> for Answer {
+    fn from(ip_addr: IpAddr) -> Answer {
+        match ip_addr {
+            IpAddr::V4(ip_addr) => Answer::A(ip_addr),
+            IpAddr::V6(ip_addr) => Answer::AAAA(ip_addr),
+        }
+    }
+}
+
+pub struct Resolver {
+    server: SocketAddr,
+    timeout: Duration,
+    recursion_desired: bool,
+}
+
+impl Resolver {
+    /// Resolver sending recursive queries to `server`
+    pub fn new(server: SocketAddr) -> Resolver {
+        Resolver {
+            server: server,
+            timeout: Duration::from_secs(5),
+            recursion_desired: true,
+        }
+    }
+
+    /// Resolver sending non recursive queries to the authoritative nameserver `server`
+    pub fn authoritative(server: SocketAddr) -> Resolver {
+        Resolver { recursion_desired: false, ..Resolver::new(server) }
+    }
+
+    /// Resolver using the first nameserver of `/etc/resolv.conf`
+    pub fn system() -> Result<Resolver> {
+        let mut content = String::new();
+        try!(try!(File::open(RESOLV_CONF)).read_to_string(&mut content));
+
+        let server = content.lines()
+            .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+            .filter(|fields| fields.len() >= 2 && fields[0] == "nameserver")
+            .filter_map(|fields| IpAddr::from_str(fields[1]).ok())
+            .next();
+
+        match server {
+            Some(ip_addr) => Ok(Resolver::new(SocketAddr::new(ip_addr, DNS_PORT))),
+            None => Err(Error::Dns(format!("No nameserver found in {}", RESOLV_CONF))),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the answers to the query, no answer if the name doesn't exist
+    pub fn query(&self, name: &str, query_type: QueryType) -> Result<Vec<Answer>> {
+        trace!("DNS query to {}: {} {:?}", self.server, name, query_type);
+
+        let id = query_id();
+        let request = try!(encode_query(id, name, query_type, self.recursion_desired));
+
+        let bind_addr = match self.server {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = try!(UdpSocket::bind(bind_addr));
+
+        let mut buffer = [0u8; 4096];
+        let mut last_error = None;
+
+        for _ in 0..ATTEMPTS {
+            try!(socket.send_to(&request, self.server));
+
+            match self.receive(&socket, id, &mut buffer) {
+                Ok(size) => {
+                    let answers = match is_truncated(&buffer[..size]) {
+                        true => {
+                            debug!("Truncated DNS response from {}, query again over TCP",
+                                   self.server);
+                            try!(self.query_tcp(id, &request))
+                        }
+                        false => try!(decode_response(id, &buffer[..size])),
+                    };
+                    trace!("DNS answers from {}: {:?}", self.server, answers);
+                    return Ok(answers);
+                }
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        Err(Error::Dns(format!("No response from {} for {}: {}",
+                               self.server,
+                               name,
+                               last_error.map_or("unknown error".to_string(), |e| e.to_string()))))
+    }
+
+    /// Waits for the response to the query `id`, ignoring datagrams from other hosts or with
+    /// another ID, e.g. late responses to a previous attempt. Returns its size.
+    fn receive(&self, socket: &UdpSocket, id: u16, buffer: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out"));
+            }
+            try!(socket.set_read_timeout(Some(deadline - now)));
+
+            let (size, from) = try!(socket.recv_from(buffer));
+            if from == self.server && read_u16(&buffer[..size], 0) == Some(id) {
+                return Ok(size);
+            }
+            debug!("Unexpected DNS response from {} ignored", from);
+        }
+    }
+
+    /// Sends the query over TCP, each message being prefixed with its length
+    fn query_tcp(&self, id: u16, request: &[u8]) -> Result<Vec<Answer>> {
+        let mut stream = try!(TcpStream::connect_timeout(&self.server, self.timeout));
+        try!(stream.set_read_timeout(Some(self.timeout)));
+        try!(stream.set_write_timeout(Some(self.timeout)));
+
+        let mut message = Vec::with_capacity(request.len() + 2);
+        push_u16(&mut message, request.len() as u16);
+        message.extend_from_slice(request);
+        try!(stream.write_all(&message));
+
+        let mut length = [0u8; 2];
+        try!(stream.read_exact(&mut length));
+        let mut response = vec![0u8; read_u16(&length, 0).unwrap() as usize];
+        try!(stream.read_exact(&mut response));
+
+        decode_response(id, &response)
+    }
+
+    /// Returns the IP addresses of `host`, IPv4 ones first
+    pub fn lookup_host(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let mut addresses = Vec::new();
+
+        for query_type in &[QueryType::A, QueryType::AAAA] {
+            for answer in try!(self.query(host, *query_type)) {
+                match answer {
+                    Answer::A(ip_addr) => addresses.push(IpAddr::V4(ip_addr)),
+                    Answer::AAAA(ip_addr) => addresses.push(IpAddr::V6(ip_addr)),
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(addresses)
+    }
+}
+
+fn query_id() -> u16 {
+    let now = ::time::precise_time_ns();
+    (now ^ (now >> 16) ^ (now >> 32)) as u16
+}
+
+fn encode_query(id: u16, name: &str, query_type: QueryType, recursion: bool) -> Result<Vec<u8>> {
+    let mut message = Vec::with_capacity(512);
+
+    push_u16(&mut message, id);
+    push_u16(&mut message, if recursion { 0x0100 } else { 0 });
+    // One question, no answer, authority or additional record
+    push_u16(&mut message, 1);
+    push_u16(&mut message, 0);
+    push_u16(&mut message, 0);
+    push_u16(&mut message, 0);
+
+    for label in name.trim_right_matches('.').split('.').filter(|label| !label.is_empty()) {
+        if label.len() > 63 {
+            return Err(Error::Dns(format!("Invalid name: {}", name)));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+
+    push_u16(&mut message, query_type.code());
+    // Class IN
+    push_u16(&mut message, 1);
+
+    Ok(message)
+}
+
+/// Whether the TC flag is set: the response didn't fit in a datagram
+fn is_truncated(message: &[u8]) -> bool {
+    read_u16(message, 2).map_or(false, |flags| flags & 0x0200 != 0)
+}
+
+fn decode_response(id: u16, message: &[u8]) -> Result<Vec<Answer>> {
+    if message.len() < 12 || read_u16(message, 0) != Some(id) {
+        return Err(Error::Dns("Invalid DNS response".to_string()));
+    }
+
+    let flags = read_u16(message, 2).unwrap();
+    match flags & 0x000F {
+        0 => (),
+        // Name error: the name doesn't exist
+        3 => return Ok(Vec::new()),
+        rcode => return Err(Error::Dns(format!("DNS error, response code {}", rcode))),
+    }
+
+    let questions = read_u16(message, 4).unwrap();
+    let answers = read_u16(message, 6).unwrap();
+
+    let mut position = 12;
+    for _ in 0..questions {
+        let (_, next) = try!(read_name(message, position));
+        position = next + 4;
+    }
+
+    let mut result = Vec::new();
+    for _ in 0..answers {
+        let (_, next) = try!(read_name(message, position));
+        position = next;
+
+        let header = match (read_u16(message, position), read_u16(message, position + 8)) {
+            (Some(type_), Some(length)) => (type_, length as usize),
+            _ => return Err(truncated()),
+        };
+        let (type_, length) = header;
+        let rdata_start = position + 10;
+        let rdata_end = rdata_start + length;
+        if rdata_end > message.len() {
+            return Err(truncated());
+        }
+        let rdata = &message[rdata_start..rdata_end];
+
+        result.push(match type_ {
+            1 if length == 4 => Answer::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+            2 => Answer::NS(try!(read_name(message, rdata_start)).0),
+            16 => Answer::TXT(try!(read_character_strings(rdata))),
+            28 if length == 16 => {
+                let mut segments = [0u16; 8];
+                for (index, segment) in segments.iter_mut().enumerate() {
+                    *segment = read_u16(rdata, index * 2).unwrap();
+                }
+                Answer::AAAA(Ipv6Addr::new(segments[0],
+                                           segments[1],
+                                           segments[2],
+                                           segments[3],
+                                           segments[4],
+                                           segments[5],
+                                           segments[6],
+                                           segments[7]))
+            }
+            other => Answer::Other(other),
+        });
+
+        position = rdata_end;
+    }
+
+    Ok(result)
+}
+
+/// Reads a possibly compressed name, returns it with the position following it
+fn read_name(message: &[u8], start: usize) -> Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut position = start;
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let length = match message.get(position) {
+            Some(length) => *length as usize,
+            None => return Err(truncated()),
+        };
+
+        if length == 0 {
+            position += 1;
+            break;
+        }
+
+        if length & 0xC0 == 0xC0 {
+            // Compression pointer
+            let pointer = match read_u16(message, position) {
+                Some(pointer) => (pointer & 0x3FFF) as usize,
+                None => return Err(truncated()),
+            };
+            jumps += 1;
+            if jumps > 64 {
+                return Err(Error::Dns("Invalid DNS response, compression loop".to_string()));
+            }
+            if end.is_none() {
+                end = Some(position + 2);
+            }
+            position = pointer;
+            continue;
+        }
+
+        let label_end = position + 1 + length;
+        if label_end > message.len() {
+            return Err(truncated());
+        }
+        labels.push(String::from_utf8_lossy(&message[position + 1..label_end]).into_owned());
+        position = label_end;
+    }
+
+    Ok((labels.join(".").to_lowercase(), end.unwrap_or(position)))
+}
+
+fn read_character_strings(rdata: &[u8]) -> Result<Vec<String>> {
+    let mut strings = Vec::new();
+    let mut position = 0;
+
+    while position < rdata.len() {
+        let end = position + 1 + rdata[position] as usize;
+        if end > rdata.len() {
+            return Err(truncated());
+        }
+        strings.push(String::from_utf8_lossy(&rdata[position + 1..end]).into_owned());
+        position = end;
+    }
+
+    Ok(strings)
+}
+
+fn read_u16(buffer: &[u8], position: usize) -> Option<u16> {
+    if position + 2 > buffer.len() {
+        return None;
+    }
+    Some(((buffer[position] as u16) << 8) | buffer[position + 1] as u16)
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.push((value >> 8) as u8);
+    buffer.push(value as u8);
+}
+
+fn truncated() -> Error {
+    Error::Dns("Invalid DNS response, truncated message".to_string())
+}
+
+/// Local DNS stand-in, for tests
+#[cfg(test)]
+pub mod testing {
+    use super::{push_u16, read_name, read_u16};
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Record served by a `LocalServer`, the name is matched case-insensitively
+    #[derive(Clone)]
+    pub struct LocalRecord {
+        pub name: String,
+        pub type_: u16,
+        pub rdata: Vec<u8>,
+    }
+
+    pub fn txt(name: &str, value: &str) -> LocalRecord {
+        let mut rdata = vec![value.len() as u8];
+        rdata.extend_from_slice(value.as_bytes());
+        LocalRecord {
+            name: name.to_string(),
+            type_: 16,
+            rdata: rdata,
+        }
+    }
+
+    pub fn a(name: &str, octets: [u8; 4]) -> LocalRecord {
+        LocalRecord {
+            name: name.to_string(),
+            type_: 1,
+            rdata: octets.to_vec(),
+        }
+    }
+
+    pub fn ns(name: &str, host: &str) -> LocalRecord {
+        let mut rdata = Vec::new();
+        for label in host.split('.').filter(|label| !label.is_empty()) {
+            rdata.push(label.len() as u8);
+            rdata.extend_from_slice(label.as_bytes());
+        }
+        rdata.push(0);
+        LocalRecord {
+            name: name.to_string(),
+            type_: 2,
+            rdata: rdata,
+        }
+    }
+
+    /// UDP server answering with its current records, which can be changed while it runs
+    pub struct LocalServer {
+        pub addr: SocketAddr,
+        pub records: Arc<Mutex<Vec<LocalRecord>>>,
+    }
+
+    impl LocalServer {
+        pub fn start(records: Vec<LocalRecord>) -> LocalServer {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let addr = socket.local_addr().unwrap();
+            let records = Arc::new(Mutex::new(records));
+            let served = records.clone();
+
+            thread::spawn(move || {
+                let mut buffer = [0u8; 512];
+                loop {
+                    let (size, from) = match socket.recv_from(&mut buffer) {
+                        Ok(received) => received,
+                        Err(_) => return,
+                    };
+                    let response = answer(&buffer[..size], &served.lock().unwrap());
+                    let _ = socket.send_to(&response, from);
+                }
+            });
+
+            LocalServer {
+                addr: addr,
+                records: records,
+            }
+        }
+    }
+
+    /// Response to the query, with the matching records
+    pub fn answer(query: &[u8], records: &[LocalRecord]) -> Vec<u8> {
+        let (name, end) = read_name(query, 12).unwrap();
+        let type_ = read_u16(query, end).unwrap();
+
+        let matching = records.iter()
+            .filter(|record| record.name.trim_right_matches('.').to_lowercase() == name &&
+                             record.type_ == type_)
+            .collect::<Vec<&LocalRecord>>();
+
+        let mut response = Vec::new();
+        response.extend_from_slice(&query[0..2]);
+        // Response, authoritative answer
+        push_u16(&mut response, 0x8400);
+        push_u16(&mut response, 1);
+        push_u16(&mut response, matching.len() as u16);
+        push_u16(&mut response, 0);
+        push_u16(&mut response, 0);
+        response.extend_from_slice(&query[12..end + 4]);
+
+        for record in matching {
+            // Pointer to the name of the question
+            push_u16(&mut response, 0xC00C);
+            push_u16(&mut response, record.type_);
+            push_u16(&mut response, 1);
+            push_u16(&mut response, 0);
+            push_u16(&mut response, 300);
+            push_u16(&mut response, record.rdata.len() as u16);
+            response.extend_from_slice(&record.rdata);
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::testing::{LocalServer, a, answer, ns, txt};
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+    use std::thread;
+
+    #[test]
+    fn encode_name() {
+        let query = super::encode_query(0x1234, "www.Example.com.", QueryType::A, true).unwrap();
+
+        assert_eq!(vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 3, b'w', b'w', b'w', 7,
+                        b'E', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 1,
+                        0, 1],
+                   query);
+    }
+
+    #[test]
+    fn query_local_server() {
+        let server = LocalServer::start(vec![a("home.example.com", [100, 3, 5, 4]),
+                                             txt("_acme-challenge.example.com", "token"),
+                                             ns("example.com", "ns1.example.net")]);
+        let resolver = Resolver::new(server.addr);
+
+        assert_eq!(vec![Answer::A(Ipv4Addr::new(100, 3, 5, 4))],
+                   resolver.query("home.example.com", QueryType::A).unwrap());
+        assert_eq!(vec![Answer::TXT(vec!["token".to_string()])],
+                   resolver.query("_acme-challenge.example.com.", QueryType::TXT).unwrap());
+        assert_eq!(vec![Answer::NS("ns1.example.net".to_string())],
+                   resolver.query("example.com", QueryType::NS).unwrap());
+        assert!(resolver.query("unknown.example.com", QueryType::A).unwrap().is_empty());
+    }
+
+    #[test]
+    fn mismatched_id_ignored() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (size, from) = socket.recv_from(&mut buffer).unwrap();
+            let response = answer(&buffer[..size], &[a("home.example.com", [100, 3, 5, 4])]);
+            let mut spoofed = answer(&buffer[..size], &[a("home.example.com", [6, 6, 6, 6])]);
+            spoofed[0] ^= 0xFF;
+            socket.send_to(&spoofed, from).unwrap();
+            socket.send_to(&response, from).unwrap();
+        });
+
+        assert_eq!(vec![Answer::A(Ipv4Addr::new(100, 3, 5, 4))],
+                   Resolver::new(addr).query("home.example.com", QueryType::A).unwrap());
+    }
+
+    #[test]
+    fn truncated_response() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let listener = TcpListener::bind(addr).unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (size, from) = socket.recv_from(&mut buffer).unwrap();
+            let mut response = answer(&buffer[..size], &[]);
+            // TC flag
+            response[2] |= 0x02;
+            socket.send_to(&response, from).unwrap();
+        });
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).unwrap();
+            let mut query = vec![0u8; ((length[0] as usize) << 8) | length[1] as usize];
+            stream.read_exact(&mut query).unwrap();
+
+            let response = answer(&query, &[a("home.example.com", [100, 3, 5, 4])]);
+            stream.write_all(&[(response.len() >> 8) as u8, response.len() as u8]).unwrap();
+            stream.write_all(&response).unwrap();
+        });
+
+        assert_eq!(vec![Answer::A(Ipv4Addr::new(100, 3, 5, 4))],
+                   Resolver::new(addr).query("home.example.com", QueryType::A).unwrap());
+    }
+
+    #[test]
+    fn name_error() {
+        // NXDOMAIN response to a query for "a."
+        let response = [0, 1, 0x84, 0x03, 0, 1, 0, 0, 0, 0, 0, 0, 1, b'a', 0, 0, 1, 0, 1];
+        assert!(super::decode_response(1, &response).unwrap().is_empty());
+    }
+
+    #[test]
+    fn compression_loop() {
+        let message = [0, 1, 0x84, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xC0, 12];
+        assert!(super::read_name(&message, 12).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use error::Error;
+use error::Result;
+use shell;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use toml;
+
+/// Printed instead of the value of a secret
+pub const REDACTED: &'static str = "********";
+
+/// Environment variable holding the API key, if not given on the command line
+pub const APIKEY_ENV: &'static str = "GDU_APIKEY";
+
+/// Sensitive value, e.g. an API key. Never printed, even with `Debug`, to keep it out of logs.
+#[derive(Clone, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: &str) -> Secret {
+        Secret(value.to_string())
+    }
+
+    /// The value itself, to be sent to the DNS provider only
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Where a secret is read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecretSource {
+    /// Given on the command line
+    Value(Secret),
+    /// Environment variable
+    Env(String),
+    /// File holding the secret, e.g. a systemd credential or a Docker secret
+    File(String),
+    /// Shell command printing the secret on stdout, e.g. "pass show gandi"
+    Command(String),
+}
+
+impl fmt::Display for SecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &SecretSource::Value(_) => write!(f, "command line"),
+            &SecretSource::Env(ref name) => write!(f, "environment variable {}", name),
+            &SecretSource::File(ref path) => write!(f, "file '{}'", path),
+            &SecretSource::Command(ref command) => write!(f, "command '{}'", command),
+        }
+    }
+}
+
+impl SecretSource {
+    /// Source of the secret `name` in the settings of a provider: exactly one of `name` itself,
+    /// `name_file`, `name_command` or `name_env`, e.g. "apikey_file"
+    pub fn from_settings(settings: &toml::Table, name: &str) -> Result<SecretSource> {
+        let mut sources = Vec::new();
+
+        for suffix in &["", "_file", "_command", "_env"] {
+            let setting = format!("{}{}", name, suffix);
+            let value = match settings.get(&setting) {
+                Some(&toml::Value::String(ref value)) => value.clone(),
+                Some(_) => return Err(Error::Credentials(format!("{} must be a string", setting))),
+                None => continue,
+            };
+
+            sources.push(match *suffix {
+                "" => SecretSource::Value(Secret::new(&value)),
+                "_file" => SecretSource::File(value),
+                "_command" => SecretSource::Command(value),
+                _ => SecretSource::Env(value),
+            });
+        }
+
+        match sources.len() {
+            1 => Ok(sources.remove(0)),
+            _ => {
+                Err(Error::Credentials(format!("Exactly one of {0}, {0}_file, {0}_command or \
+                                                {0}_env is required",
+                                               name)))
+            }
+        }
+    }
+
+    /// Reads the secret, without the trailing newline of files and commands. Fails if it is
+    /// missing or empty.
+    pub fn read(&self) -> Result<Secret> {
+        let value = match self {
+            &SecretSource::Value(ref secret) => secret.expose().to_string(),
+            &SecretSource::Env(ref name) if name == APIKEY_ENV => {
+                try!(env::var(name).map_err(|_| {
+                    Error::Credentials(format!("An API key is required: use --apikey, \
+                                                --apikey-file, --apikey-command or the {} \
+                                                environment variable",
+                                               name))
+                }))
+            }
+            &SecretSource::Env(ref name) => {
+                try!(env::var(name).map_err(|_| {
+                    Error::Credentials(format!("Environment variable {} not set", name))
+                }))
+            }
+            &SecretSource::File(ref path) => {
+                let mut value = String::new();
+                try!(File::open(path)
+                    .and_then(|mut file| file.read_to_string(&mut value))
+                    .map_err(|err| {
+                        Error::Credentials(format!("Failed to read file '{}': {}", path, err))
+                    }));
+                value
+            }
+            &SecretSource::Command(ref command) => try!(run(command)),
+        };
+
+        let value = value.trim_right_matches(|c| c == '\n' || c == '\r');
+
+        match value.is_empty() {
+            true => Err(Error::Credentials(format!("Empty secret read from {}", self))),
+            false => Ok(Secret::new(value)),
+        }
+    }
+}
+
+fn run(command: &str) -> Result<String> {
+    let output = try!(shell::command(command).output());
+
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => {
+            Err(Error::Credentials(format!("Command '{}' failed: {}", command, output.status)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use toml;
+
+    #[test]
+    fn redacted() {
+        let secret = Secret::new("my-api-key");
+
+        assert_eq!("********", secret.to_string());
+        assert_eq!("********", format!("{:?}", secret));
+        assert_eq!("Value(********)",
+                   format!("{:?}", SecretSource::Value(secret.clone())));
+        assert_eq!("my-api-key", secret.expose());
+    }
+
+    #[test]
+    fn from_env() {
+        env::set_var("GDU_TEST_APIKEY", "my-api-key");
+
+        assert_eq!(Secret::new("my-api-key"),
+                   SecretSource::Env("GDU_TEST_APIKEY".to_string()).read().unwrap());
+        assert!(SecretSource::Env("GDU_TEST_NO_APIKEY".to_string()).read().is_err());
+    }
+
+    #[test]
+    fn from_file() {
+        let path = env::temp_dir().join("gdu-test-apikey");
+        File::create(&path).unwrap().write_all(b"my-api-key\n").unwrap();
+
+        let secret = SecretSource::File(path.to_str().unwrap().to_string()).read();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(Secret::new("my-api-key"), secret.unwrap());
+        assert!(SecretSource::File("/nonexistent/apikey".to_string()).read().is_err());
+    }
+
+    fn settings(content: &str) -> toml::Table {
+        toml::Parser::new(content).parse().unwrap()
+    }
+
+    #[test]
+    fn from_settings() {
+        assert_eq!(SecretSource::File("/run/secrets/gandi".to_string()),
+                   SecretSource::from_settings(&settings("apikey_file = \"/run/secrets/gandi\""),
+                                               "apikey")
+                       .unwrap());
+        assert_eq!(SecretSource::Env("GANDI_KEY".to_string()),
+                   SecretSource::from_settings(&settings("apikey_env = \"GANDI_KEY\""), "apikey")
+                       .unwrap());
+        // Several sources
+        assert!(SecretSource::from_settings(&settings("apikey = \"key\"\napikey_env = \
+                                                       \"GANDI_KEY\""),
+                                            "apikey")
+            .is_err());
+        // No source
+        assert!(SecretSource::from_settings(&settings(""), "apikey").is_err());
+        // Not a string
+        assert!(SecretSource::from_settings(&settings("apikey = 42"), "apikey").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn from_command() {
+        assert_eq!(Secret::new("my-api-key"),
+                   SecretSource::Command("echo my-api-key".to_string()).read().unwrap());
+        assert!(SecretSource::Command("true".to_string()).read().is_err());
+        assert!(SecretSource::Command("echo my-api-key; exit 1".to_string()).read().is_err());
+    }
+}
@@ -0,0 +1,16 @@
+use std::process::Command;
+
+/// Command running `command` with the shell of the platform, e.g. "pass show gandi"
+pub fn command(command: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
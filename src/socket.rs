@@ -0,0 +1,235 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+// Outbound connections leaving from a given source address or network interface, e.g. to
+// detect the public address of each uplink of a multi-WAN router. The standard library can't
+// bind a socket before connecting it, so sockets are set up with libc, on Linux only.
+
+/// Where outbound connections leave from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// Local address the socket is bound to, e.g. of a WAN interface
+    Address(IpAddr),
+    /// Network interface the socket is bound to with SO_BINDTODEVICE, e.g. "ppp0"
+    Interface(String),
+}
+
+impl Source {
+    /// Whether `address` can be reached from this source: a source address only reaches
+    /// addresses of its own family
+    pub fn reaches(&self, address: &SocketAddr) -> bool {
+        match (self, address) {
+            (&Source::Address(IpAddr::V4(_)), &SocketAddr::V6(_)) |
+            (&Source::Address(IpAddr::V6(_)), &SocketAddr::V4(_)) => false,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Source::Address(ref ip_addr) => write!(f, "address {}", ip_addr),
+            &Source::Interface(ref name) => write!(f, "interface {}", name),
+        }
+    }
+}
+
+/// Connects to `address` from `source`, waiting at most `timeout`
+#[cfg(target_os = "linux")]
+pub fn connect_from(address: &SocketAddr,
+                    source: &Source,
+                    timeout: Duration)
+                    -> io::Result<TcpStream> {
+    use libc;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let domain = match address {
+        &SocketAddr::V4(_) => libc::AF_INET,
+        &SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owns the socket from now on, to close it on failure
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    try!(match source {
+        &Source::Address(ip_addr) => linux::bind(fd, &SocketAddr::new(ip_addr, 0)),
+        &Source::Interface(ref name) => linux::bind_to_device(fd, name),
+    });
+
+    // Non-blocking connection, not to wait longer than `timeout`
+    try!(stream.set_nonblocking(true));
+    try!(linux::connect(stream.as_raw_fd(), address, timeout));
+    if let Some(err) = try!(stream.take_error()) {
+        return Err(err);
+    }
+    try!(stream.set_nonblocking(false));
+
+    Ok(stream)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn connect_from(_: &SocketAddr, source: &Source, _: Duration) -> io::Result<TcpStream> {
+    Err(io::Error::new(io::ErrorKind::Other,
+                       format!("Connections from the {} are only supported on Linux", source)))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use libc;
+    use std::ffi::CString;
+    use std::io;
+    use std::mem;
+    use std::net::SocketAddr;
+    use std::os::unix::io::RawFd;
+    use std::time::Duration;
+
+    pub fn bind(fd: RawFd, address: &SocketAddr) -> io::Result<()> {
+        let (storage, length) = sockaddr(address);
+        let result = unsafe {
+            libc::bind(fd,
+                       &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+                       length)
+        };
+
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Requires the CAP_NET_RAW capability before Linux 5.7
+    pub fn bind_to_device(fd: RawFd, name: &str) -> io::Result<()> {
+        let name = try!(CString::new(name).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Invalid interface name")
+        }));
+        let result = unsafe {
+            libc::setsockopt(fd,
+                             libc::SOL_SOCKET,
+                             libc::SO_BINDTODEVICE,
+                             name.as_ptr() as *const libc::c_void,
+                             name.as_bytes_with_nul().len() as libc::socklen_t)
+        };
+
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Starts the connection of the non-blocking socket `fd`, and waits until it is writable.
+    /// The outcome of the connection is then the pending error of the socket, if any.
+    pub fn connect(fd: RawFd, address: &SocketAddr, timeout: Duration) -> io::Result<()> {
+        let (storage, length) = sockaddr(address);
+        let result = unsafe {
+            libc::connect(fd,
+                          &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+                          length)
+        };
+
+        if result == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(err);
+        }
+
+        let mut pollfd = libc::pollfd {
+            fd: fd,
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+        let millis = timeout.as_secs() * 1000 + (timeout.subsec_nanos() / 1_000_000) as u64;
+        let millis = if millis > libc::c_int::max_value() as u64 {
+            libc::c_int::max_value()
+        } else {
+            millis as libc::c_int
+        };
+
+        match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+            0 => Err(io::Error::new(io::ErrorKind::TimedOut, "Connection timed out")),
+            ready if ready < 0 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    fn sockaddr(address: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+        let length = match address {
+            &SocketAddr::V4(ref address) => {
+                let sin = unsafe {
+                    &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in)
+                };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = address.port().to_be();
+                sin.sin_addr = libc::in_addr { s_addr: u32::from(*address.ip()).to_be() };
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            &SocketAddr::V6(ref address) => {
+                let sin6 = unsafe {
+                    &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6)
+                };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = address.port().to_be();
+                sin6.sin6_flowinfo = address.flowinfo();
+                sin6.sin6_addr.s6_addr = address.ip().octets();
+                sin6.sin6_scope_id = address.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+
+        (storage, length as libc::socklen_t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, SocketAddr, TcpListener};
+    use std::time::Duration;
+
+    #[test]
+    fn reaches() {
+        let ipv4: SocketAddr = "100.3.5.5:443".parse().unwrap();
+        let ipv6: SocketAddr = "[2a01:ca07::3117]:443".parse().unwrap();
+
+        assert!(Source::Address("192.168.1.2".parse().unwrap()).reaches(&ipv4));
+        assert!(!Source::Address("192.168.1.2".parse().unwrap()).reaches(&ipv6));
+        assert!(Source::Interface("ppp0".to_string()).reaches(&ipv6));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn from_source_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let source = Source::Address("127.0.0.2".parse().unwrap());
+
+        let stream = connect_from(&listener.local_addr().unwrap(),
+                                  &source,
+                                  Duration::from_secs(1))
+            .unwrap();
+
+        assert_eq!("127.0.0.2".parse::<IpAddr>().unwrap(),
+                   stream.local_addr().unwrap().ip());
+        assert_eq!(stream.local_addr().unwrap(),
+                   listener.accept().unwrap().1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn unknown_source_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        // Documentation address, not assigned to any interface
+        let source = Source::Address("192.0.2.1".parse().unwrap());
+
+        assert!(connect_from(&listener.local_addr().unwrap(), &source, Duration::from_secs(1))
+            .is_err());
+    }
+}
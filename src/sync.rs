@@ -0,0 +1,200 @@
+use dns::ChangeSet;
+use dns::Record;
+use dns::RecordType;
+use dns::RecordValue;
+use dns::ZoneRecord;
+use error::Result;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Computes the changes to apply to the `current` records of a zone, so that it declares the
+/// `desired` ones.
+///
+/// Records are compared by set of records sharing the same name and type: a set is left as is
+/// if all its values and TTLs are already declared, a single record is updated, otherwise the
+/// whole set is deleted and created again.
+/// Only record types supported by `RecordType` are handled, others are ignored, as well as
+/// current records whose value can't be parsed.
+pub fn diff<'a>(current: &'a [ZoneRecord], desired: &'a [ZoneRecord]) -> Result<ChangeSet<'a>> {
+    let mut record_sets: BTreeMap<(&str, String), (Vec<Entry>, Vec<Entry>)> = BTreeMap::new();
+
+    for record in current {
+        match entry(record) {
+            Ok(Some(entry)) => {
+                record_sets.entry((&record.name[..], record.type_.to_uppercase()))
+                    .or_insert((Vec::new(), Vec::new()))
+                    .0
+                    .push(entry)
+            }
+            Ok(None) => (),
+            Err(err) => {
+                warn!("Current record '{}' with type {} ignored: {}",
+                      record.name,
+                      record.type_,
+                      err)
+            }
+        }
+    }
+
+    for record in desired {
+        match try!(entry(record)) {
+            Some(entry) => {
+                record_sets.entry((&record.name[..], record.type_.to_uppercase()))
+                    .or_insert((Vec::new(), Vec::new()))
+                    .1
+                    .push(entry)
+            }
+            None => {
+                warn!("Record '{}' with type {} ignored, this type can't be synchronized",
+                      record.name,
+                      record.type_)
+            }
+        }
+    }
+
+    let mut changes = ChangeSet::new();
+
+    for (&(name, _), &(ref current, ref desired)) in record_sets.iter() {
+        if is_same_set(current, desired) {
+            continue;
+        }
+
+        if current.len() == 1 && desired.len() == 1 {
+            changes.update(record(name, &desired[0]), desired[0].value.clone());
+            continue;
+        }
+
+        if !current.is_empty() {
+            changes.delete(Record::of_type(name, current[0].type_.clone()));
+        }
+
+        for desired_entry in desired {
+            changes.create(record(name, desired_entry), desired_entry.value.clone());
+        }
+    }
+
+    Ok(changes)
+}
+
+/// A record of a set, with its typed value
+#[derive(Debug, PartialEq)]
+struct Entry {
+    type_: RecordType,
+    ttl: u32,
+    value: RecordValue,
+}
+
+/// Returns `None` if the type of the record isn't supported
+fn entry(record: &ZoneRecord) -> Result<Option<Entry>> {
+    let record_type = match RecordType::from_str(&record.type_) {
+        Ok(record_type) => record_type,
+        Err(_) => return Ok(None),
+    };
+
+    let value = try!(RecordValue::parse(&record_type, &record.value));
+
+    Ok(Some(Entry {
+        type_: record_type,
+        ttl: record.ttl,
+        value: value,
+    }))
+}
+
+fn is_same_set(current: &[Entry], desired: &[Entry]) -> bool {
+    current.len() == desired.len() && desired.iter().all(|entry| current.contains(entry))
+}
+
+fn record<'a>(name: &'a str, entry: &Entry) -> Record<'a> {
+    let mut record = Record::of_type(name, entry.type_.clone());
+    record.ttl = Some(entry.ttl);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+    use dns::ZoneRecord;
+
+    fn record(name: &str, type_: &str, ttl: u32, value: &str) -> ZoneRecord {
+        ZoneRecord {
+            name: name.to_string(),
+            type_: type_.to_string(),
+            ttl: ttl,
+            value: value.to_string(),
+        }
+    }
+
+    fn diff_lines(current: &[ZoneRecord], desired: &[ZoneRecord]) -> Vec<String> {
+        diff(current, desired).unwrap().iter().map(|change| change.to_string()).collect()
+    }
+
+    #[test]
+    fn nothing_to_do() {
+        let records = vec![record("@", "A", 300, "100.3.5.4"),
+                           record("@", "NS", 10800, "ns1.gandi.net."),
+                           record("www", "CNAME", 10800, "webredir.vip.gandi.net.")];
+
+        assert!(diff(&records, &records).unwrap().is_empty());
+    }
+
+    #[test]
+    fn create_update_and_delete() {
+        let current = vec![record("@", "A", 300, "100.3.5.4"),
+                           record("old", "A", 300, "100.3.5.4"),
+                           record("www", "CNAME", 10800, "webredir.vip.gandi.net.")];
+        let desired = vec![record("@", "A", 300, "100.3.5.5"),
+                           record("new", "AAAA", 600, "2a01:ca07::3117"),
+                           record("@", "MX", 10800, "10 spool.mail.gandi.net.")];
+
+        assert_eq!(vec!["~ @ 300 A 100.3.5.5".to_string(),
+                        "+ @ 10800 MX 10 spool.mail.gandi.net.".to_string(),
+                        "+ new 600 AAAA 2a01:ca07::3117".to_string(),
+                        "- old A".to_string(),
+                        "- www CNAME".to_string()],
+                   diff_lines(&current, &desired));
+    }
+
+    #[test]
+    fn same_txt_value_differently_written() {
+        let current = vec![record("@", "TXT", 300, "v=spf1 a ?all")];
+        let desired = vec![record("@", "TXT", 300, "\"v=spf1 a ?all\"")];
+
+        assert!(diff(&current, &desired).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_desired_value() {
+        let desired = vec![record("@", "MX", 300, "spool.mail.gandi.net.")];
+
+        assert!(diff(&[], &desired).is_err());
+    }
+
+    #[test]
+    fn invalid_current_value() {
+        let current = vec![record("@", "MX", 300, "10 not a name"),
+                           record("www", "CNAME", 10800, "webredir.vip.gandi.net.")];
+        let desired = vec![record("www", "CNAME", 10800, "webredir.vip.gandi.net.")];
+
+        assert!(diff(&current, &desired).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ttl_only_update() {
+        let current = vec![record("@", "A", 10800, "100.3.5.4")];
+        let desired = vec![record("@", "A", 300, "100.3.5.4")];
+
+        assert_eq!(vec!["~ @ 300 A 100.3.5.4".to_string()],
+                   diff_lines(&current, &desired));
+    }
+
+    #[test]
+    fn replace_record_set() {
+        let current = vec![record("@", "A", 300, "100.3.5.4")];
+        let desired = vec![record("@", "A", 300, "100.3.5.4"), record("@", "A", 300, "100.3.5.5")];
+
+        assert_eq!(vec!["- @ A".to_string(),
+                        "+ @ 300 A 100.3.5.4".to_string(),
+                        "+ @ 300 A 100.3.5.5".to_string()],
+                   diff_lines(&current, &desired));
+    }
+}
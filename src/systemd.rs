@@ -0,0 +1,134 @@
+use log::{LogLevel, LogRecord};
+use logging;
+use std::env;
+use std::io;
+use std::process;
+use std::time::Duration;
+
+// systemd integration: service manager notifications (sd_notify) for daemon mode, and entries
+// of the journal with structured fields, so that e.g. `journalctl RECORD=vpn` shows the runs
+// of a record.
+
+/// Socket of the native journal protocol
+const JOURNAL_SOCKET: &'static str = "/run/systemd/journal/socket";
+
+const SYSLOG_IDENTIFIER: &'static str = "gdu";
+
+/// Sends `state` to the service manager, e.g. "READY=1". Does nothing if gdu isn't run by
+/// systemd, or with an abstract notification socket.
+pub fn notify(state: &str) {
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+
+    if path.starts_with('@') {
+        debug!("Abstract notification socket not supported: {}", path);
+        return;
+    }
+
+    trace!("Notify systemd: {}", state);
+    if let Err(err) = send(state.as_bytes(), &path) {
+        warn!("Failed to notify systemd: {}", err);
+    }
+}
+
+/// Interval of the watchdog keep-alive pings expected by the service manager, if enabled for
+/// this process
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+
+    env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .map(|usec| Duration::from_millis(usec / 1000))
+}
+
+/// Sends the log entry to the journal, with the structured fields of the run
+pub fn log_to_journal(record: &LogRecord) -> io::Result<()> {
+    let mut entry = Vec::new();
+    append_field(&mut entry, "MESSAGE", &record.args().to_string());
+    append_field(&mut entry, "PRIORITY", priority(record.level()));
+    append_field(&mut entry, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+    append_field(&mut entry, "CODE_MODULE", record.target());
+    for (name, value) in logging::fields() {
+        append_field(&mut entry, name, &value);
+    }
+
+    send(&entry, JOURNAL_SOCKET)
+}
+
+/// Syslog priority of the level
+fn priority(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Error => "3",
+        LogLevel::Warn => "4",
+        LogLevel::Info => "6",
+        LogLevel::Debug | LogLevel::Trace => "7",
+    }
+}
+
+/// Appends a field in the native journal protocol: "NAME=value\n", or the name, the length on
+/// 64 bits little endian and the value if it spans several lines
+fn append_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+
+    if value.contains('\n') {
+        entry.push(b'\n');
+        let length = value.len() as u64;
+        for shift in 0..8 {
+            entry.push((length >> (8 * shift)) as u8);
+        }
+    } else {
+        entry.push(b'=');
+    }
+
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+#[cfg(unix)]
+fn send(datagram: &[u8], path: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = try!(UnixDatagram::unbound());
+    socket.send_to(datagram, path).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn send(_: &[u8], _: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "systemd is only available on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::append_field;
+    use std::env;
+    use std::time::Duration;
+
+    #[test]
+    fn fields() {
+        let mut entry = Vec::new();
+        append_field(&mut entry, "MESSAGE", "Record updated");
+        append_field(&mut entry, "MESSAGE", "a\nb");
+
+        assert_eq!(b"MESSAGE=Record updated\nMESSAGE\n\x03\0\0\0\0\0\0\0a\nb\n".to_vec(),
+                   entry);
+    }
+
+    #[test]
+    fn watchdog() {
+        env::set_var("WATCHDOG_USEC", "30000000");
+        assert_eq!(Some(Duration::from_secs(30)), super::watchdog_interval());
+
+        env::set_var("WATCHDOG_PID", "1");
+        assert_eq!(None, super::watchdog_interval());
+
+        env::remove_var("WATCHDOG_PID");
+        env::remove_var("WATCHDOG_USEC");
+    }
+}
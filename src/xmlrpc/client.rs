@@ -8,40 +8,69 @@
 
 // Rust XML-RPC library
 
-use hyper;
-use hyper::header::Headers;
+use error::Error;
+use error::Result;
+use http::{HttpClient, HttpSettings, Idempotence};
+use hyper::header::ContentType;
 use std::string;
-use std::io::Read;
 use xmlrpc::protocol::{Request, Response};
 
 pub struct Client {
     url: string::String,
+    http: HttpClient,
 }
 
 impl Client {
-    pub fn new(s: &str) -> Client {
-        Client { url: s.to_string() }
+    /// Fails if the HTTP client can't be built, e.g. with an invalid CA file
+    pub fn new(s: &str, http: &HttpSettings) -> Result<Client> {
+        Ok(Client {
+            url: s.to_string(),
+            http: try!(HttpClient::new(http)),
+        })
     }
 
-    pub fn remote_call(&self, request: &Request) -> Option<Response> {
-        let http_client = hyper::Client::new();
-        let mut headers = Headers::new();
-        headers.set_raw("Content-Type", vec![b"text/xml".to_vec()]);
-        headers.set_raw("User-Agent", vec![b"rust-xmlrpc".to_vec()]);
-
+    /// Sends `request` and returns the server response. The request is retried on failure
+    /// only if `idempotence` allows it.
+    ///
+    /// An XML-RPC fault sent back by the server is returned as an `Error::XmlRpc`, a response
+    /// status other than a success as an `Error::HttpStatus`.
+    pub fn remote_call(&self, request: &Request, idempotence: Idempotence) -> Result<Response> {
         debug!("Send XMLRPC request to: {}", &self.url);
-        trace!("XMLRPC body: {}", &request.body);
+        trace!("XMLRPC body: {}", &request.redacted_body());
+
+        let content_type = ContentType("text/xml".parse().unwrap());
+        // FIXME: use to_xml() somehow?
+        let response = try!(self.http
+            .post(&self.url, content_type, &request.body, idempotence)
+            .and_then(|response| response.success(&self.url)));
+
+        trace!("Reponse body: {}", &response.body);
 
-        let response = http_client.post(&self.url)
-            .headers(headers)
-            .body(&request.body) // FIXME: use to_xml() somehow?
-            .send();
+        let response = Response::new(&response.body);
+
+        match response.fault() {
+            Some(fault) => Err(Error::XmlRpc(format!("{} failed: {}", request.method, fault))),
+            None => Ok(response),
+        }
+    }
+}
 
-        let mut body = String::new();
-        response.ok().unwrap().read_to_string(&mut body).ok().expect("could not read response");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error;
+    use http::{HttpSettings, Idempotence};
+    use http::testing::{Behavior, StandIn};
+    use xmlrpc::protocol::Request;
 
-        trace!("Reponse body: {}", &body);
+    #[test]
+    fn http_status() {
+        let server = StandIn::start(vec![Behavior::Respond("404 Not Found", "<html></html>")]);
+        let client = Client::new(&server.url, &HttpSettings::default()).unwrap();
 
-        Some(Response::new(&body)) // FIXME: change to a Result<> type
+        match client.remote_call(&Request::new("domain.info"), Idempotence::Idempotent) {
+            Err(Error::HttpStatus(_)) => (),
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
     }
 }
@@ -8,13 +8,26 @@
 
 // Rust XML-RPC library
 
+use regex::Regex;
 use rustc_serialize::{Encodable, Decodable};
+use secret::{REDACTED, Secret};
+use std::fmt;
 use xmlrpc::encoding;
 
-#[derive(Debug)]
 pub struct Request {
     pub method: String,
     pub body: String,
+    /// Encoded secret arguments, redacted from logs
+    secrets: Vec<String>,
+}
+
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("method", &self.method)
+            .field("body", &self.redacted_body())
+            .finish()
+    }
 }
 
 #[derive(Debug)]
@@ -31,6 +44,7 @@ impl Request {
             <methodCall><methodName>{}</methodName>\
                 <params>",
                           method),
+            secrets: Vec::new(),
         }
     }
 
@@ -40,6 +54,21 @@ impl Request {
         self
     }
 
+    /// Same as `argument`, the secret being redacted from `redacted_body`
+    pub fn secret_argument(mut self, secret: &Secret) -> Request {
+        self.secrets.push(encoding::encode(&secret.expose().to_string()));
+        self.argument(&secret.expose().to_string())
+    }
+
+    /// Body with the secret arguments redacted, to be logged
+    pub fn redacted_body(&self) -> String {
+        let redacted = encoding::encode(&REDACTED.to_string());
+
+        self.secrets
+            .iter()
+            .fold(self.body.clone(), |body, secret| body.replace(secret, &redacted))
+    }
+
     pub fn finalize(mut self) -> Request {
         self.body = self.body + "</params></methodCall>";
         self
@@ -54,6 +83,21 @@ impl Response {
     pub fn result<T: Decodable>(&self) -> Result<Vec<T>, encoding::DecoderError> {
         encoding::decode(&self.body)
     }
+
+    /// Returns the fault string if the server answered with a `<fault>`.
+    pub fn fault(&self) -> Option<String> {
+        if !self.body.contains("<fault>") {
+            return None;
+        }
+
+        let regex = Regex::new(r"<name>faultString</name>\s*<value>\s*(?:<string>)?([^<]*)")
+            .unwrap();
+
+        match regex.captures(&self.body).and_then(|caps| caps.at(1)) {
+            Some(fault_string) => Some(fault_string.trim().to_string()),
+            None => Some("unknown fault".to_string()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -83,6 +127,20 @@ mod tests {
         assert_eq!(expected, &*request.body);
     }
 
+    #[test]
+    fn test_redacted_secret() {
+        let secret = ::secret::Secret::new("my-api-key");
+        let request = super::Request::new("domain.info")
+            .secret_argument(&secret)
+            .argument(&"example.com".to_string())
+            .finalize();
+
+        assert!(request.body.contains("<string>my-api-key</string>"));
+        assert!(!request.redacted_body().contains("my-api-key"));
+        assert!(request.redacted_body().contains("<string>********</string>"));
+        assert!(!format!("{:?}", request).contains("my-api-key"));
+    }
+
     #[test]
     fn test_decode() {
         let response = super::Response {
@@ -139,4 +197,36 @@ mod tests {
         assert_eq!(4.2, result.key2);
         assert_eq!(true, result.key3);
     }
+
+    #[test]
+    fn test_fault() {
+        let response = super::Response::new("<?xml version='1.0'?>
+            <methodResponse>
+            <fault>
+            <value><struct>
+            <member>
+            <name>faultCode</name>
+            <value><int>510042</int></value>
+            </member>
+            <member>
+            <name>faultString</name>
+            <value><string>Error on object : OBJECT_ZONE (CAUSE_NOTFOUND)</string></value>
+            </member>
+            </struct></value>
+            </fault>
+            </methodResponse>");
+
+        assert_eq!(Some("Error on object : OBJECT_ZONE (CAUSE_NOTFOUND)".to_string()),
+                   response.fault());
+    }
+
+    #[test]
+    fn test_no_fault() {
+        let response = super::Response::new("<?xml version='1.0'?>
+            <methodResponse>
+            <params><param><value><boolean>1</boolean></value></param></params>
+            </methodResponse>");
+
+        assert_eq!(None, response.fault());
+    }
 }
@@ -0,0 +1,404 @@
+use dns::ZoneRecord;
+use error::Error;
+use error::Result;
+use std::iter::Peekable;
+use std::str::Chars;
+
+// RFC 1035 master file parser
+//
+// Supported: $ORIGIN, $TTL, relative and absolute owner names, blank owner (previous one is
+// used), optional TTL and class, parentheses spanning several lines, comments and quoted
+// character-strings (several of them for TXT records), with \X and \DDD escapes.
+// Record names are returned relative to the zone, as used by DNS providers ("@" for the apex).
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Bare(String),
+    Quoted(String),
+}
+
+impl Token {
+    fn as_str(&self) -> &str {
+        match self {
+            &Token::Bare(ref value) => value,
+            &Token::Quoted(ref value) => value,
+        }
+    }
+
+    fn to_rdata(&self) -> String {
+        match self {
+            &Token::Bare(ref value) => value.clone(),
+            &Token::Quoted(ref value) => format!("\"{}\"", value),
+        }
+    }
+}
+
+/// A logical entry of the file, which may span several lines between parentheses
+struct Entry {
+    line: usize,
+    blank_owner: bool,
+    tokens: Vec<Token>,
+}
+
+/// Parses the master file `input` of the zone `domain`.
+pub fn parse(input: &str, domain: &str) -> Result<Vec<ZoneRecord>> {
+    let zone = format!("{}.", domain.trim_right_matches('.').to_lowercase());
+
+    let mut origin = zone.clone();
+    let mut default_ttl = None;
+    let mut last_ttl = None;
+    let mut last_owner: Option<String> = None;
+    let mut records = Vec::new();
+
+    for entry in try!(entries(input)) {
+        let line = entry.line;
+        let mut tokens = entry.tokens.iter();
+
+        if !entry.blank_owner && entry.tokens[0].as_str().starts_with('$') {
+            let directive = tokens.next().unwrap().as_str().to_uppercase();
+            let argument = try!(tokens.next()
+                .ok_or(error(line, &format!("missing value for {}", directive))));
+
+            match directive.as_ref() {
+                "$ORIGIN" => origin = absolute_name(argument.as_str(), &origin),
+                "$TTL" => {
+                    default_ttl = Some(try!(parse_ttl(argument.as_str())
+                        .ok_or(error(line, &format!("invalid TTL '{}'", argument.as_str())))))
+                }
+                _ => return Err(error(line, &format!("unsupported directive {}", directive))),
+            }
+            continue;
+        }
+
+        let owner = if entry.blank_owner {
+            try!(last_owner.clone().ok_or(error(line, "no previous owner name")))
+        } else {
+            absolute_name(tokens.next().unwrap().as_str(), &origin)
+        };
+        last_owner = Some(owner.clone());
+
+        // TTL and class are both optional, in any order
+        let mut ttl = None;
+        let mut type_ = None;
+        while let Some(token) = tokens.next() {
+            let value = token.as_str().to_uppercase();
+            if ttl.is_none() && parse_ttl(&value).is_some() {
+                ttl = parse_ttl(&value);
+            } else if value == "IN" {
+                continue;
+            } else if value == "CH" || value == "HS" {
+                return Err(error(line, &format!("unsupported class {}", value)));
+            } else {
+                type_ = Some(value);
+                break;
+            }
+        }
+
+        let type_ = try!(type_.ok_or(error(line, "missing record type")));
+
+        let ttl = try!(ttl.or(default_ttl)
+            .or(last_ttl)
+            .ok_or(error(line, "no TTL and no $TTL defined")));
+        last_ttl = Some(ttl);
+
+        let value = tokens.map(|token| token.to_rdata()).collect::<Vec<String>>().join(" ");
+
+        if value.is_empty() {
+            return Err(error(line, "missing record data"));
+        }
+
+        if type_ == "SOA" {
+            // Managed by the DNS provider
+            debug!("Ignore SOA record, line {}", line);
+            continue;
+        }
+
+        records.push(ZoneRecord {
+            name: try!(zone_name(&owner, &zone).ok_or(error(line, &format!("'{}' is out of zone",
+                                                                            owner)))),
+            type_: type_,
+            ttl: ttl,
+            value: value,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Splits `input` into entries, without comments
+fn entries(input: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0;
+
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let blank_owner = line.starts_with(' ') || line.starts_with('\t');
+
+        let mut entry = current.take().unwrap_or(Entry {
+            line: line_number,
+            blank_owner: blank_owner,
+            tokens: Vec::new(),
+        });
+
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                ';' => break,
+                '(' => depth += 1,
+                ')' => {
+                    if depth == 0 {
+                        return Err(error(line_number, "unbalanced parentheses"));
+                    }
+                    depth -= 1;
+                }
+                '"' => {
+                    let mut value = String::new();
+                    let mut closed = false;
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\\' => {
+                                match try!(decimal_escape(&mut chars, line_number)) {
+                                    // Kept escaped in the value, as other escapes
+                                    Some(escaped) if escaped == '"' || escaped == '\\' => {
+                                        value.push(c);
+                                        value.push(escaped);
+                                    }
+                                    Some(escaped) => value.push(escaped),
+                                    None => {
+                                        value.push(c);
+                                        if let Some(escaped) = chars.next() {
+                                            value.push(escaped);
+                                        }
+                                    }
+                                }
+                            }
+                            '"' => {
+                                closed = true;
+                                break;
+                            }
+                            _ => value.push(c),
+                        }
+                    }
+                    if !closed {
+                        return Err(error(line_number, "unterminated quoted string"));
+                    }
+                    entry.tokens.push(Token::Quoted(value));
+                }
+                c if c.is_whitespace() => (),
+                _ => {
+                    let mut value = c.to_string();
+                    while let Some(&next) = chars.peek() {
+                        if next.is_whitespace() || next == ';' || next == '(' || next == ')' ||
+                           next == '"' {
+                            break;
+                        }
+                        value.push(next);
+                        chars.next();
+                    }
+                    entry.tokens.push(Token::Bare(value));
+                }
+            }
+        }
+
+        if depth > 0 {
+            current = Some(entry);
+        } else if !entry.tokens.is_empty() {
+            entries.push(entry);
+        }
+    }
+
+    if depth > 0 {
+        return Err(error(input.lines().count(), "unbalanced parentheses"));
+    }
+
+    Ok(entries)
+}
+
+/// Decodes the `\DDD` escape (RFC 1035 section 5.1) following a backslash, if any, into the
+/// character of the byte value
+fn decimal_escape(chars: &mut Peekable<Chars>, line_number: usize) -> Result<Option<char>> {
+    let mut digits = String::new();
+    while digits.len() < 3 {
+        match chars.peek() {
+            Some(&digit) if digit.is_digit(10) => digits.push(digit),
+            _ => break,
+        }
+        chars.next();
+    }
+
+    match digits.len() {
+        0 => Ok(None),
+        3 => {
+            match digits.parse::<u8>() {
+                Ok(byte) => Ok(Some(byte as char)),
+                Err(_) => Err(error(line_number, &format!("escaped value \\{} above 255", digits))),
+            }
+        }
+        _ => Err(error(line_number, &format!("invalid escape \\{}", digits))),
+    }
+}
+
+fn absolute_name(name: &str, origin: &str) -> String {
+    let name = name.to_lowercase();
+
+    if name == "@" {
+        origin.to_string()
+    } else if name.ends_with('.') {
+        name
+    } else {
+        format!("{}.{}", name, origin)
+    }
+}
+
+/// Returns `fqdn` relative to `zone`, "@" for the zone apex
+fn zone_name(fqdn: &str, zone: &str) -> Option<String> {
+    if fqdn == zone {
+        return Some("@".to_string());
+    }
+
+    let suffix = format!(".{}", zone);
+    if fqdn.ends_with(&suffix) {
+        Some(fqdn[..fqdn.len() - suffix.len()].to_string())
+    } else {
+        None
+    }
+}
+
+/// Parses a TTL in seconds, or with BIND units such as "1h30m"
+fn parse_ttl(value: &str) -> Option<u32> {
+    if !value.starts_with(|c: char| c.is_digit(10)) {
+        return None;
+    }
+
+    if let Ok(seconds) = value.parse::<u32>() {
+        return Some(seconds);
+    }
+
+    let mut ttl = 0u32;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_digit(10) {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            's' | 'S' => 1,
+            'm' | 'M' => 60,
+            'h' | 'H' => 3600,
+            'd' | 'D' => 86400,
+            'w' | 'W' => 604800,
+            _ => return None,
+        };
+
+        let count = match number.parse::<u32>() {
+            Ok(count) => count,
+            Err(_) => return None,
+        };
+        ttl = match count.checked_mul(unit).and_then(|seconds| ttl.checked_add(seconds)) {
+            Some(ttl) => ttl,
+            None => return None,
+        };
+        number.clear();
+    }
+
+    if number.is_empty() { Some(ttl) } else { None }
+}
+
+fn error(line: usize, message: &str) -> Error {
+    Error::ZoneFile(format!("line {}: {}", line, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_ttl, zone_name};
+    use dns::ZoneRecord;
+
+    fn record(name: &str, type_: &str, ttl: u32, value: &str) -> ZoneRecord {
+        ZoneRecord {
+            name: name.to_string(),
+            type_: type_.to_string(),
+            ttl: ttl,
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_zone() {
+        let zone = "$ORIGIN example.com.\n\
+                    $TTL 3h\n\
+                    @ IN SOA ns1.gandi.net. hostmaster.gandi.net. (\n\
+                    \t1 ; serial\n\
+                    \t10800 3600 604800 10800 )\n\
+                    @ 300 IN A 100.3.5.4 ; home\n\
+                    \tIN AAAA 2a01:ca07::3117\n\
+                    www CNAME webredir.vip.gandi.net.\n\
+                    mail.example.com. IN 600 MX 10 spool.mail.gandi.net.\n\
+                    @ TXT \"v=spf1 include:_mailcust.gandi.net\" \"?all\"\n\
+                    $ORIGIN lan.example.com.\n\
+                    nas A 192.168.0.10\n";
+
+        assert_eq!(vec![record("@", "A", 300, "100.3.5.4"),
+                        record("@", "AAAA", 10800, "2a01:ca07::3117"),
+                        record("www", "CNAME", 10800, "webredir.vip.gandi.net."),
+                        record("mail", "MX", 600, "10 spool.mail.gandi.net."),
+                        record("@",
+                               "TXT",
+                               10800,
+                               "\"v=spf1 include:_mailcust.gandi.net\" \"?all\""),
+                        record("nas.lan", "A", 10800, "192.168.0.10")],
+                   parse(zone, "example.com").unwrap());
+    }
+
+    #[test]
+    fn quoted_semicolon_is_not_a_comment() {
+        let records = parse("@ 300 TXT \"a;b\" ; comment", "example.com").unwrap();
+        assert_eq!("\"a;b\"", records[0].value);
+    }
+
+    #[test]
+    fn decimal_escapes() {
+        let records = parse("@ 300 TXT \"v=spf1\\032-all\" \"\\034\\\\\"", "example.com").unwrap();
+        assert_eq!("\"v=spf1 -all\" \"\\\"\\\\\"", records[0].value);
+        assert!(parse("@ 300 TXT \"a\\256\"", "example.com").is_err());
+        assert!(parse("@ 300 TXT \"a\\32\"", "example.com").is_err());
+    }
+
+    #[test]
+    fn out_of_zone() {
+        assert!(parse("www.example.org. 300 A 100.3.5.4", "example.com").is_err());
+    }
+
+    #[test]
+    fn missing_ttl() {
+        assert!(parse("www A 100.3.5.4", "example.com").is_err());
+    }
+
+    #[test]
+    fn unbalanced_parentheses() {
+        assert!(parse("@ 300 IN SOA ns1 hostmaster ( 1 2 3 4 5", "example.com").is_err());
+    }
+
+    #[test]
+    fn ttl_units() {
+        assert_eq!(Some(300), parse_ttl("300"));
+        assert_eq!(Some(5400), parse_ttl("1h30m"));
+        assert_eq!(Some(86400), parse_ttl("1D"));
+        assert_eq!(None, parse_ttl("A"));
+        assert_eq!(None, parse_ttl("1x"));
+    }
+
+    #[test]
+    fn ttl_overflow() {
+        assert_eq!(None, parse_ttl("99999999w"));
+        assert_eq!(None, parse_ttl("4294967295s1s"));
+    }
+
+    #[test]
+    fn names_relative_to_zone() {
+        assert_eq!(Some("@".to_string()), zone_name("example.com.", "example.com."));
+        assert_eq!(Some("www".to_string()), zone_name("www.example.com.", "example.com."));
+        assert_eq!(None, zone_name("wwwexample.com.", "example.com."));
+    }
+}